@@ -18,4 +18,33 @@ defmodule Elixlsx.Native do
     files = write_excel_nif(workbook)
     :zip.create('workbook.xlsx', files, [:memory])
   end
+
+  def read_excel_nif(_files) do
+    :erlang.nif_error("nif not loaded")
+  end
+
+  # Reads an .xlsx file, given as its contents or its path, into an %Elixlsx.Workbook{}
+  # that write_excel/1 can write back. Contents are told apart by the zip local file header.
+  # Packages with malformed parts give {:error, :invalid_xlsx}.
+  def read_excel(<<"PK", 3, 4, _::binary>> = xlsx), do: unzip_and_read(xlsx)
+
+  def read_excel(path) do
+    with {:ok, xlsx} <- File.read(path), do: unzip_and_read(xlsx)
+  end
+
+  defp unzip_and_read(xlsx) do
+    with {:ok, files} <- :zip.unzip(xlsx, [:memory, file_filter: &xml_part?/1]) do
+      files = for {name, content} <- files, do: {List.to_string(name), content}
+      {sheets, workbook} = files |> read_excel_nif() |> Map.pop(:sheets)
+      sheets = Enum.map(sheets, &Map.merge(%Elixlsx.Sheet{}, &1))
+      {:ok, %Elixlsx.Workbook{} |> Map.merge(workbook) |> Map.put(:sheets, sheets)}
+    end
+  rescue
+    ArgumentError -> {:error, :invalid_xlsx}
+  end
+
+  # Only the XML parts are read, so images and other media are left packed.
+  defp xml_part?({:zip_file, name, _info, _comment, _offset, _comp_size}) do
+    Path.extname(name) in [".xml", ".rels"]
+  end
 end
@@ -0,0 +1,329 @@
+use std::collections::HashMap;
+use crate::util::Float;
+use crate::wb_compiler::{CellStyle, Font};
+use crate::workbook::{CachedValue, CellValue};
+
+/// Estimates column widths from the text the cells of each column display.
+///
+/// Widths are kept in multiples of the default font's digit width, the unit of `<col width>`.
+pub struct ColumnWidths {
+    default_font: Font,
+    widths: HashMap<i32, f64>,
+}
+
+impl ColumnWidths {
+    pub fn new(default_font: &Font) -> Self {
+        ColumnWidths {
+            default_font: default_font.clone(),
+            widths: HashMap::new(),
+        }
+    }
+
+    pub fn measure(&mut self, col: i32, value: &CellValue, style: &CellStyle) {
+        let text = match value {
+            CellValue::String(string) | CellValue::NumberText(string) | CellValue::Error(string) => {
+                string.clone()
+            }
+            CellValue::Number(num) => format_number(num, style),
+            CellValue::Formula(formula) => match &formula.value {
+                Some(CachedValue::Number(num)) => format_number(num, style),
+                Some(CachedValue::Bool(x)) => x.to_string().to_uppercase(),
+                Some(CachedValue::String(x)) | Some(CachedValue::Error(x)) => x.clone(),
+                _ => return,
+            },
+            _ => return,
+        };
+        // Wrapped text breaks at line feeds; elsewhere they don't show at all.
+        let width = if style.alignment.wrap_text {
+            text.split('\n').map(text_width).fold(0.0, f64::max)
+        } else {
+            text_width(&text.replace('\n', ""))
+        };
+
+        let font = match &style.font {
+            Some(font) => font.inherit(&self.default_font),
+            _ => self.default_font.clone(),
+        };
+        let mut width = width * font.size.0 / self.default_font.size.0;
+        if font.bold == Some(true) {
+            width *= 1.1;
+        }
+
+        let entry = self.widths.entry(col).or_insert(0.0);
+        if width > *entry {
+            *entry = width;
+        }
+    }
+
+    /// The measured columns, in order.
+    pub fn columns(&self) -> Vec<i32> {
+        let mut re: Vec<i32> = self.widths.keys().cloned().collect();
+        re.sort();
+        re
+    }
+
+    /// The `<col width>` fitting `col`: its widest text plus the cell padding (5 pixels of
+    /// the 7 a digit takes), in 1/256ths as Excel stores them, and at most Excel's 255.
+    pub fn get(&self, col: i32) -> Option<Float> {
+        self.widths.get(&col).map(|&width| {
+            let width = (width + 5.0 / 7.0).min(255.0);
+            Float((width * 256.0).ceil() / 256.0)
+        })
+    }
+}
+
+/// Width of `text` in digit widths of the font it is set in, by rough character classes.
+fn text_width(text: &str) -> f64 {
+    text.chars()
+        .map(|c| match c {
+            'i' | 'j' | 'l' | '.' | ',' | ':' | ';' | '\'' | '|' | '!' | ' ' => 0.5,
+            'f' | 'r' | 't' | 'I' | '(' | ')' | '[' | ']' | '-' | '/' => 0.7,
+            'm' | 'w' | 'M' | 'W' | '@' | '%' => 1.5,
+            'A'..='Z' => 1.2,
+            // East Asian wide characters and emoji.
+            '\u{1100}'..='\u{115F}'
+            | '\u{2E80}'..='\u{A4CF}'
+            | '\u{AC00}'..='\u{D7A3}'
+            | '\u{F900}'..='\u{FAFF}'
+            | '\u{FE30}'..='\u{FE4F}'
+            | '\u{FF00}'..='\u{FF60}'
+            | '\u{FFE0}'..='\u{FFE6}'
+            | '\u{1F300}'..='\u{1FAFF}'
+            | '\u{20000}'..='\u{3FFFD}' => 2.0,
+            _ => 1.0,
+        })
+        .sum()
+}
+
+enum Part {
+    Literal(char),
+    Code(char),
+}
+
+/// Splits the first section of a format code into what is shown as is and what stands for
+/// part of the value; only the length of the result matters, so colors, conditions and
+/// fill characters are dropped.
+fn first_section(code: &str) -> Vec<Part> {
+    let mut re = vec![];
+    let mut chars = code.chars();
+    let mut quoted = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => quoted = !quoted,
+            _ if quoted => re.push(Part::Literal(c)),
+            ';' => break,
+            '\\' => re.extend(chars.next().map(Part::Literal)),
+            '_' => {
+                chars.next();
+                re.push(Part::Literal(' '));
+            }
+            '*' => {
+                chars.next();
+            }
+            '[' => {
+                let bracket: String = chars.by_ref().take_while(|&x| x != ']').collect();
+                if let Some(currency) = bracket.strip_prefix('$') {
+                    // `[$€-407]`: a currency symbol and its locale.
+                    let symbol = currency.split('-').next().unwrap_or("");
+                    re.extend(symbol.chars().map(Part::Literal));
+                } else if bracket.chars().all(|x| "hHmMsS".contains(x)) {
+                    // Elapsed time, `[h]:mm`.
+                    re.extend(bracket.chars().map(Part::Code));
+                }
+            }
+            _ => re.push(Part::Code(c)),
+        }
+    }
+    re
+}
+
+/// Roughly the text Excel displays for the number `num` in `style`'s number format.
+fn format_number(num: &str, style: &CellStyle) -> String {
+    let code = match style.numfmt.format_code() {
+        Some(code) => code,
+        // Built-in ids without a documented code are the locale's dates and times.
+        _ if style.is_date() => "yyyy/mm/dd",
+        _ => "General",
+    };
+    let parts = first_section(code);
+    if parts.is_empty() {
+        return "".to_string();
+    }
+    let is_general = code.eq_ignore_ascii_case("General")
+        || parts.iter().any(|x| matches!(x, Part::Code('@')));
+    let value = num.parse::<f64>().unwrap_or(0.0);
+    if is_general {
+        format_general(num, value)
+    } else if style.is_date() {
+        format_date(&parts)
+    } else {
+        format_decimal(value, &parts)
+    }
+}
+
+/// General shows up to 11 characters, switching to scientific notation for large numbers.
+fn format_general(num: &str, value: f64) -> String {
+    if num.len() <= 11 {
+        num.to_string()
+    } else if value.abs() < 1e11 && value.abs() >= 1e-4 {
+        num.chars().take(11).collect()
+    } else {
+        scientific(value, 5)
+    }
+}
+
+fn format_date(parts: &[Part]) -> String {
+    let text: String = parts
+        .iter()
+        .map(|x| match x {
+            Part::Literal(c) | Part::Code(c) => c.to_ascii_lowercase(),
+        })
+        .collect();
+    text.replace("mmmm", "September")
+        .replace("dddd", "Wednesday")
+        .replace("mmm", "Sep")
+        .replace("ddd", "Wed")
+        .replace("am/pm", "PM")
+        .replace("a/p", "P")
+}
+
+/// Writes the number where its first digit placeholder is, leaving out the rest of the
+/// placeholders, separators and exponent signs.
+fn format_decimal(value: f64, parts: &[Part]) -> String {
+    let is_code = |part: &Part, chars: &str| match part {
+        Part::Code(c) => chars.contains(*c),
+        _ => false,
+    };
+    let mut value = value;
+    if parts.iter().any(|x| is_code(x, "%")) {
+        value *= 100.0;
+    }
+    let point = parts.iter().position(|x| is_code(x, "."));
+    let exponent = parts.iter().position(|x| is_code(x, "Ee"));
+    let decimals = match point {
+        Some(point) => parts[point..exponent.filter(|&x| x > point).unwrap_or(parts.len())]
+            .iter()
+            .filter(|x| is_code(x, "0#?"))
+            .count(),
+        _ => 0,
+    };
+    let thousands = parts[..point.unwrap_or(parts.len())]
+        .iter()
+        .any(|x| is_code(x, ","));
+
+    let number = if exponent.is_some() {
+        scientific(value.abs(), decimals)
+    } else {
+        let number = format!("{:.*}", decimals, value.abs());
+        if thousands {
+            group_thousands(&number)
+        } else {
+            number
+        }
+    };
+
+    let mut re = String::new();
+    if value < 0.0 {
+        re.push('-');
+    }
+    let mut written = false;
+    for (i, part) in parts.iter().enumerate() {
+        match part {
+            Part::Code(c)
+                if "0#?.,".contains(*c)
+                    || (exponent.is_some_and(|x| i > x) && "+-".contains(*c)) =>
+            {
+                if !written && *c != ',' {
+                    re.push_str(&number);
+                    written = true;
+                }
+            }
+            Part::Code('E') | Part::Code('e') => (),
+            Part::Literal(c) | Part::Code(c) => re.push(*c),
+        }
+    }
+    re
+}
+
+/// `1.23E+04`, where Rust would write `1.23E4`.
+fn scientific(value: f64, decimals: usize) -> String {
+    let number = format!("{:.*E}", decimals, value);
+    match number.find('E') {
+        Some(i) => {
+            let exponent: i32 = number[i + 1..].parse().unwrap_or(0);
+            format!("{}E{}{:02}", &number[..i], if exponent < 0 { '-' } else { '+' }, exponent.abs())
+        }
+        _ => number,
+    }
+}
+
+fn group_thousands(number: &str) -> String {
+    let (int, frac) = match number.find('.') {
+        Some(i) => number.split_at(i),
+        _ => (number, ""),
+    };
+    let mut re = String::with_capacity(number.len() + int.len() / 3);
+    for (i, c) in int.chars().enumerate() {
+        if i > 0 && (int.len() - i) % 3 == 0 {
+            re.push(',');
+        }
+        re.push(c);
+    }
+    re + frac
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wb_compiler::NumFmt;
+
+    fn formatted(num: &str, code: &str) -> String {
+        let style = CellStyle {
+            numfmt: NumFmt::from_format_code(code.to_string()),
+            ..Default::default()
+        };
+        format_number(num, &style)
+    }
+
+    #[test]
+    fn text_widths() {
+        assert_eq!(text_width(""), 0.0);
+        assert_eq!(text_width("il."), 1.5);
+        assert_eq!(text_width("ab"), 2.0);
+        assert_eq!(text_width("AW"), 2.7);
+        assert_eq!(text_width("漢字"), 4.0);
+    }
+
+    #[test]
+    fn general_numbers() {
+        assert_eq!(formatted("1234.5", "General"), "1234.5");
+        assert_eq!(formatted("123456789012345", "General"), "1.23457E+14");
+        assert_eq!(formatted("0.000012345678", "General"), "1.23457E-05");
+        assert_eq!(formatted("1234.56789012", "General"), "1234.567890");
+        assert_eq!(formatted("42", "@"), "42");
+    }
+
+    #[test]
+    fn number_formats() {
+        assert_eq!(formatted("1234.5", "0.00"), "1234.50");
+        assert_eq!(formatted("1234.5", "#,##0.00"), "1,234.50");
+        assert_eq!(formatted("-5", "0.0"), "-5.0");
+        assert_eq!(formatted("0.25", "0%"), "25%");
+        assert_eq!(formatted("12345", "0.00E+00"), "1.23E+04");
+        assert_eq!(formatted("1.5", "0.00\" kg\""), "1.50 kg");
+        assert_eq!(formatted("1234.4", "[$€-407] #,##0"), "€ 1,234");
+        assert_eq!(formatted("3", "[Red]0;[Blue]-0"), "3");
+        assert_eq!(formatted("3", ";;;"), "");
+    }
+
+    #[test]
+    fn date_formats() {
+        assert_eq!(formatted("43101", "d mmmm yyyy"), "d September yyyy");
+        assert_eq!(formatted("43101", "mm-dd-yy"), "mm-dd-yy");
+        let style = CellStyle {
+            numfmt: NumFmt::BuiltIn(27),
+            ..Default::default()
+        };
+        assert_eq!(format_number("43101", &style), "yyyy/mm/dd");
+    }
+}
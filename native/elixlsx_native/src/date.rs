@@ -0,0 +1,222 @@
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime};
+use rustler::{Error, NifResult, Term};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use crate::util::Float;
+use crate::workbook::decode_string;
+
+/// How dates and times are turned into serial numbers, from the workbook's options.
+#[derive(Default, Clone, Copy)]
+pub struct DateOptions {
+    /// Offset from UTC in seconds that `DateTime`s are written in.
+    pub utc_offset: i32,
+    /// Count days from 1904-01-01 as Excel for the Mac used to, instead of from 1900-01-00.
+    pub date1904: bool,
+}
+
+/// Decodes `{:duration, seconds}` or an Erlang `{{y, m, d}, {h, m, s}}` into its serial
+/// number and the format code cells holding it get when they have none; `None` for any
+/// other term.
+pub fn decode_date<'a>(term: Term<'a>, options: &DateOptions) -> NifResult<Option<(String, &'static str)>> {
+    if term.is_tuple() {
+        let li = ::rustler::types::tuple::get_tuple(term)?;
+        if li.len() != 2 {
+            return Ok(None);
+        }
+        if li[0].is_tuple() {
+            let ((y, m, d), (h, mm, s)) = term.decode::<((i32, u32, u32), (u32, u32, u32))>()?;
+            let datetime = NaiveDate::from_ymd_opt(y, m, d)
+                .and_then(|x| x.and_hms_opt(h, mm, s))
+                .ok_or(Error::BadArg)?;
+            return Ok(Some((to_serial(datetime, options)?, "yyyy-mm-dd h:mm:ss")));
+        }
+        if li[0].is_atom() && li[0].atom_to_string()? == "duration" {
+            let seconds = li[1].decode::<Float>()?.0;
+            if !seconds.is_finite() {
+                return Err(Error::BadArg);
+            }
+            return Ok(Some(((seconds / 86400.0).to_string(), "[h]:mm:ss")));
+        }
+    }
+    Ok(None)
+}
+
+/// Like `decode_date`, for the decoded map of a `%Date{}`, `%NaiveDateTime{}`,
+/// `%DateTime{}`, `%Time{}` or `%Duration{}`; `None` for other maps.
+pub fn decode_date_struct<'a>(
+    map: &HashMap<String, Term<'a>>,
+    options: &DateOptions,
+) -> NifResult<Option<(String, &'static str)>> {
+    let name = match map.get("__struct__") {
+        Some(&name) => decode_string(name)?,
+        _ => return Ok(None),
+    };
+    let get = |key: &str| -> NifResult<i64> { map.get(key).ok_or(Error::BadArg)?.decode() };
+    let get_date = || -> NifResult<NaiveDateTime> {
+        let year = i32::try_from(get("year")?).map_err(|_| Error::BadArg)?;
+        let month = u32::try_from(get("month")?).map_err(|_| Error::BadArg)?;
+        let day = u32::try_from(get("day")?).map_err(|_| Error::BadArg)?;
+        NaiveDate::from_ymd_opt(year, month, day)
+            .and_then(|x| x.and_hms_opt(0, 0, 0))
+            .ok_or(Error::BadArg)
+    };
+    let get_microseconds = || -> NifResult<i64> {
+        let (micro, _precision) = map.get("microsecond").ok_or(Error::BadArg)?.decode::<(i64, i64)>()?;
+        Ok(micro)
+    };
+    let get_time = || -> NifResult<Duration> {
+        let (h, mm, s, micro) = (get("hour")?, get("minute")?, get("second")?, get_microseconds()?);
+        if !(0..=23).contains(&h)
+            || !(0..=59).contains(&mm)
+            || !(0..=59).contains(&s)
+            || !(0..=999_999).contains(&micro)
+        {
+            return Err(Error::BadArg);
+        }
+        Ok(Duration::seconds(h * 3600 + mm * 60 + s) + Duration::microseconds(micro))
+    };
+    let get_datetime = || -> NifResult<NaiveDateTime> {
+        get_date()?.checked_add_signed(get_time()?).ok_or(Error::BadArg)
+    };
+    Ok(Some(match name.as_str() {
+        "Elixir.Date" => (to_serial(get_date()?, options)?, "yyyy-mm-dd"),
+        "Elixir.NaiveDateTime" => (to_serial(get_datetime()?, options)?, "yyyy-mm-dd h:mm:ss"),
+        // Moved from the offset it was taken in to the one the workbook is written in.
+        "Elixir.DateTime" => {
+            let offset = get("utc_offset")?
+                .checked_add(get("std_offset")?)
+                .and_then(|x| x.checked_sub(options.utc_offset as i64))
+                .and_then(|x| x.checked_mul(1000))
+                .ok_or(Error::BadArg)?;
+            let datetime = get_datetime()?
+                .checked_sub_signed(Duration::milliseconds(offset))
+                .ok_or(Error::BadArg)?;
+            (to_serial(datetime, options)?, "yyyy-mm-dd h:mm:ss")
+        }
+        "Elixir.Time" => (to_days(get_time()?).to_string(), "h:mm:ss"),
+        // Years and months have no fixed length, so only the other units are taken.
+        "Elixir.Duration" => {
+            if get("year")? != 0 || get("month")? != 0 {
+                return Err(Error::BadArg);
+            }
+            let units = [
+                (get("week")?, 7 * 86400),
+                (get("day")?, 86400),
+                (get("hour")?, 3600),
+                (get("minute")?, 60),
+                (get("second")?, 1),
+            ];
+            let micro = get_microseconds()?;
+            // Summed up in microseconds, as `Duration`'s constructors panic on overflow.
+            let micro = units
+                .iter()
+                .try_fold(0i64, |acc, &(n, seconds)| acc.checked_add(n.checked_mul(seconds)?))
+                .and_then(|x| x.checked_mul(1_000_000)?.checked_add(micro))
+                .ok_or(Error::BadArg)?;
+            (to_days(Duration::microseconds(micro)).to_string(), "[h]:mm:ss")
+        }
+        _ => return Ok(None),
+    }))
+}
+
+/// The serial number of `datetime`: days since 1900-01-00, or since 1904-01-01 with
+/// `date1904`. Day 0 holds the times of day on their own.
+fn to_serial(datetime: NaiveDateTime, options: &DateOptions) -> NifResult<String> {
+    let epoch = if options.date1904 {
+        NaiveDate::from_ymd_opt(1904, 1, 1)
+    } else {
+        NaiveDate::from_ymd_opt(1899, 12, 31)
+    };
+    let epoch = epoch.and_then(|x| x.and_hms_opt(0, 0, 0)).ok_or(Error::BadArg)?;
+    let mut days = to_days(datetime - epoch);
+    if days < 0.0 || datetime.year() > 9999 {
+        return Err(Error::BadArg);
+    }
+    // Excel counts 1900 as a leap year, as Lotus 1-2-3 did, so from March 1900 on its
+    // serial numbers are one more than the days elapsed.
+    if !options.date1904 && days >= 60.0 {
+        days += 1.0;
+    }
+    Ok(days.to_string())
+}
+
+fn to_days(duration: Duration) -> f64 {
+    let seconds = duration.num_seconds();
+    let micro = (duration - Duration::seconds(seconds)).num_microseconds().unwrap_or(0);
+    (seconds as f64 + micro as f64 / 1e6) / 86400.0
+}
+
+/// Whether a number format code shows a date or time: its first section has one of the
+/// codes y, m, d, h or s outside of quotes, escapes and brackets, or an elapsed time such
+/// as `[h]`. Text formats like `"yy"@` are not dates.
+pub fn is_date_format(code: &str) -> bool {
+    let mut chars = code.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                chars.by_ref().find(|&x| x == '"');
+            }
+            '\\' | '_' | '*' => {
+                chars.next();
+            }
+            '[' => {
+                let bracket: String = chars.by_ref().take_while(|&x| x != ']').collect();
+                if !bracket.is_empty() && bracket.chars().all(|x| "hHmMsS".contains(x)) {
+                    return true;
+                }
+            }
+            ';' => return false,
+            'y' | 'Y' | 'm' | 'M' | 'd' | 'D' | 'h' | 'H' | 's' | 'S' => return true,
+            _ => (),
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn serial(y: i32, m: u32, d: u32, date1904: bool) -> Option<String> {
+        let datetime = NaiveDate::from_ymd_opt(y, m, d)?.and_hms_opt(0, 0, 0)?;
+        let options = DateOptions {
+            date1904,
+            ..Default::default()
+        };
+        to_serial(datetime, &options).ok()
+    }
+
+    #[test]
+    fn serials_around_the_1900_leap_day() {
+        assert_eq!(serial(1899, 12, 30, false), None);
+        assert_eq!(serial(1899, 12, 31, false), Some("0".to_string()));
+        assert_eq!(serial(1900, 1, 1, false), Some("1".to_string()));
+        assert_eq!(serial(1900, 2, 28, false), Some("59".to_string()));
+        assert_eq!(serial(1900, 3, 1, false), Some("61".to_string()));
+        assert_eq!(serial(9999, 12, 31, false), Some("2958465".to_string()));
+        assert_eq!(serial(10000, 1, 1, false), None);
+    }
+
+    #[test]
+    fn serials_in_the_1904_system() {
+        assert_eq!(serial(1903, 12, 31, true), None);
+        assert_eq!(serial(1904, 1, 1, true), Some("0".to_string()));
+        assert_eq!(serial(1904, 1, 2, true), Some("1".to_string()));
+        assert_eq!(serial(1904, 3, 1, true), Some("60".to_string()));
+    }
+
+    #[test]
+    fn times_are_fractions_of_a_day() {
+        assert_eq!(to_days(Duration::hours(18)), 0.75);
+        assert_eq!(to_days(Duration::seconds(1) + Duration::microseconds(500_000)), 1.5 / 86400.0);
+    }
+
+    #[test]
+    fn date_formats() {
+        assert!(is_date_format("yyyy-mm-dd"));
+        assert!(is_date_format("[h]:mm:ss"));
+        assert!(!is_date_format("0.00"));
+        assert!(!is_date_format("\"yy\"@"));
+        assert!(!is_date_format("[Red]0;d"));
+    }
+}
@@ -0,0 +1,551 @@
+use regex::{Captures, Regex};
+use rustler::types::ListIterator;
+use rustler::{Error, NifResult, Term};
+use std::collections::HashMap;
+use crate::util::{encode_col, from_excel_coords, to_excel_coords};
+use crate::workbook::CachedValue;
+
+/// A formula as written to `<f>`.
+pub struct Formula {
+    /// Without the leading `=`, and with the prefixes newer functions are stored with.
+    pub text: String,
+    /// The range of an array formula, `t="array" ref`.
+    pub array_ref: Option<String>,
+    /// Uses a function returning a dynamic array, so the result spills into the cells
+    /// below and to the right instead of being cut down by implicit intersection.
+    pub dynamic: bool,
+    pub value: Option<CachedValue>,
+}
+
+impl Formula {
+    /// Checks the formula, failing with `BadArg` on text that doesn't tokenize or has
+    /// unbalanced parentheses or braces.
+    pub fn new(text: &str, array_ref: Option<String>, value: Option<CachedValue>) -> NifResult<Self> {
+        let text = text.trim_start_matches('=');
+        let tokens = tokenize(text)
+            .filter(|x| is_balanced(x))
+            .ok_or(Error::BadArg)?;
+        let mut re = String::with_capacity(text.len() + 8);
+        let mut dynamic = false;
+        for token in tokens {
+            if let Token::Function(name) = token {
+                let (prefix, is_dynamic) = function_prefix(name);
+                re.push_str(prefix.unwrap_or(""));
+                dynamic = dynamic || is_dynamic;
+            }
+            re.push_str(token.text());
+        }
+        Ok(Formula {
+            text: re,
+            array_ref,
+            dynamic,
+            value,
+        })
+    }
+}
+
+/// Fills in `{row}`, `{row+1}`, `{row-1}`, ... in a `formula_template` for the row of its cell.
+pub fn expand_template(template: &str, row: i32) -> NifResult<String> {
+    lazy_static! {
+        static ref ROW: Regex = Regex::new(r"\{row\s*(?:([+-])\s*([0-9]+)\s*)?\}").unwrap();
+    }
+    let mut in_range = true;
+    let re = ROW.replace_all(template, |cap: &Captures| {
+        let offset: i32 = cap.get(2).map_or(0, |x| x.as_str().parse().unwrap_or(0));
+        let value = match cap.get(1).map(|x| x.as_str()) {
+            Some("-") => row - offset,
+            _ => row + offset,
+        };
+        in_range = in_range && (1..=1048576).contains(&value);
+        value.to_string()
+    });
+    if in_range {
+        Ok(re.into_owned())
+    } else {
+        Err(Error::BadArg)
+    }
+}
+
+/// Functions returning dynamic arrays, with the prefix Excel stores them with.
+const DYNAMIC_ARRAY_FUNCTIONS: &[(&str, &str)] = &[
+    ("FILTER", "_xlfn._xlws."),
+    ("SORT", "_xlfn._xlws."),
+    ("SORTBY", "_xlfn."),
+    ("UNIQUE", "_xlfn."),
+    ("SEQUENCE", "_xlfn."),
+    ("RANDARRAY", "_xlfn."),
+    ("TEXTSPLIT", "_xlfn."),
+    ("VSTACK", "_xlfn."),
+    ("HSTACK", "_xlfn."),
+    ("TOCOL", "_xlfn."),
+    ("TOROW", "_xlfn."),
+    ("WRAPROWS", "_xlfn."),
+    ("WRAPCOLS", "_xlfn."),
+    ("TAKE", "_xlfn."),
+    ("DROP", "_xlfn."),
+    ("CHOOSECOLS", "_xlfn."),
+    ("CHOOSEROWS", "_xlfn."),
+    ("EXPAND", "_xlfn."),
+];
+
+/// Functions added since Excel 2007, which files store with an `_xlfn.` prefix so older
+/// versions can tell them from user defined names.
+const NEWER_FUNCTIONS: &[&str] = &[
+    "ACOT", "ACOTH", "AGGREGATE", "ARABIC", "BASE", "BETA.DIST", "BETA.INV", "BINOM.DIST",
+    "BINOM.DIST.RANGE", "BINOM.INV", "BITAND", "BITLSHIFT", "BITOR", "BITRSHIFT", "BITXOR",
+    "CEILING.MATH", "CEILING.PRECISE", "CHISQ.DIST", "CHISQ.DIST.RT", "CHISQ.INV",
+    "CHISQ.INV.RT", "CHISQ.TEST", "COMBINA", "CONCAT", "CONFIDENCE.NORM", "CONFIDENCE.T",
+    "COT", "COTH", "COVARIANCE.P", "COVARIANCE.S", "CSC", "CSCH", "DAYS", "DECIMAL",
+    "ERF.PRECISE", "ERFC.PRECISE", "EXPON.DIST", "F.DIST", "F.DIST.RT", "F.INV", "F.INV.RT",
+    "F.TEST", "FILTERXML", "FLOOR.MATH", "FLOOR.PRECISE", "FORECAST.ETS",
+    "FORECAST.ETS.CONFINT", "FORECAST.ETS.SEASONALITY", "FORECAST.ETS.STAT",
+    "FORECAST.LINEAR", "FORMULATEXT", "GAMMA", "GAMMA.DIST", "GAMMA.INV", "GAMMALN.PRECISE",
+    "GAUSS", "HYPGEOM.DIST", "IFNA", "IFS", "IMCOSH", "IMCOT", "IMCSC", "IMCSCH", "IMSEC",
+    "IMSECH", "IMSINH", "IMTAN", "ISFORMULA", "ISOWEEKNUM", "LAMBDA", "LET", "LOGNORM.DIST",
+    "LOGNORM.INV", "MAXIFS", "MINIFS", "MODE.MULT", "MODE.SNGL", "MUNIT", "NEGBINOM.DIST",
+    "NETWORKDAYS.INTL", "NORM.DIST", "NORM.INV", "NORM.S.DIST", "NORM.S.INV", "NUMBERVALUE",
+    "PDURATION", "PERCENTILE.EXC", "PERCENTILE.INC", "PERCENTRANK.EXC", "PERCENTRANK.INC",
+    "PERMUTATIONA", "PHI", "POISSON.DIST", "QUARTILE.EXC", "QUARTILE.INC", "RANK.AVG",
+    "RANK.EQ", "RRI", "SEC", "SECH", "SHEET", "SHEETS", "SKEW.P", "STDEV.P", "STDEV.S",
+    "SWITCH", "T.DIST", "T.DIST.2T", "T.DIST.RT", "T.INV", "T.INV.2T", "T.TEST", "TEXTAFTER",
+    "TEXTBEFORE", "TEXTJOIN", "UNICHAR", "UNICODE", "VAR.P", "VAR.S", "WEBSERVICE",
+    "WEIBULL.DIST", "WORKDAY.INTL", "XLOOKUP", "XMATCH", "XOR", "Z.TEST",
+];
+
+/// The prefix a call of `name` needs, if it doesn't have it yet, and whether the function
+/// returns a dynamic array.
+fn function_prefix(name: &str) -> (Option<&'static str>, bool) {
+    let bare = name.trim_start_matches("_xlfn.").trim_start_matches("_xlws.");
+    let dynamic = DYNAMIC_ARRAY_FUNCTIONS
+        .iter()
+        .find(|x| x.0.eq_ignore_ascii_case(bare));
+    let prefix = match dynamic {
+        Some((_, prefix)) => Some(*prefix),
+        _ if NEWER_FUNCTIONS.iter().any(|x| x.eq_ignore_ascii_case(bare)) => Some("_xlfn."),
+        _ => None,
+    };
+    (prefix.filter(|_| bare.len() == name.len()), dynamic.is_some())
+}
+
+/// A piece of a formula; the pieces' texts put together give back the formula.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Token<'a> {
+    /// A string literal, quotes included.
+    String(&'a str),
+    Number(&'a str),
+    /// An error literal such as `#N/A`.
+    Error(&'a str),
+    /// A cell reference, `B2`, `$B2` or `$B$2`.
+    Cell(&'a str),
+    /// A sheet name and its `!`, `Sheet1!` or `'My sheet'!`.
+    Sheet(&'a str),
+    /// The name of a called function, without its `(`.
+    Function(&'a str),
+    /// Any other name: defined names, `TRUE`, the columns of `B:B`, ...
+    Name(&'a str),
+    /// A structured reference, `[@Price]` or `[[#This Row],[Price]]`.
+    Bracket(&'a str),
+    Operator(&'a str),
+    Space(&'a str),
+}
+
+impl<'a> Token<'a> {
+    pub fn text(&self) -> &'a str {
+        match *self {
+            Token::String(x)
+            | Token::Number(x)
+            | Token::Error(x)
+            | Token::Cell(x)
+            | Token::Sheet(x)
+            | Token::Function(x)
+            | Token::Name(x)
+            | Token::Bracket(x)
+            | Token::Operator(x)
+            | Token::Space(x) => x,
+        }
+    }
+}
+
+/// Splits a formula (without its `=`) into tokens; `None` if there are unterminated
+/// literals or characters that can't appear in a formula.
+pub fn tokenize<'a>(formula: &'a str) -> Option<Vec<Token<'a>>> {
+    lazy_static! {
+        static ref CELL: Regex = Regex::new(r"^\$?[A-Za-z]{1,3}\$?[0-9]+$").unwrap();
+    }
+    let mut re = vec![];
+    let mut i = 0;
+    while i < formula.len() {
+        let rest = &formula[i..];
+        let c = rest.chars().next()?;
+        let next = rest[c.len_utf8()..].chars().next();
+        let (token, len) = match c {
+            '"' => {
+                let len = quoted_len(rest)?;
+                (Token::String(&rest[..len]), len)
+            }
+            '\'' => {
+                let len = quoted_len(rest)?;
+                if !rest[len..].starts_with('!') {
+                    return None;
+                }
+                (Token::Sheet(&rest[..len + 1]), len + 1)
+            }
+            '[' => {
+                let len = bracket_len(rest)?;
+                (Token::Bracket(&rest[..len]), len)
+            }
+            '#' => match CachedValue::ERRORS
+                .iter()
+                .find(|x| rest.get(..x.len()).is_some_and(|y| y.eq_ignore_ascii_case(x)))
+            {
+                Some(x) => (Token::Error(&rest[..x.len()]), x.len()),
+                // The spill range operator, `A1#`.
+                _ => (Token::Operator(&rest[..1]), 1),
+            },
+            _ if c.is_ascii_digit() || (c == '.' && next.is_some_and(|x| x.is_ascii_digit())) => {
+                let len = number_len(rest);
+                (Token::Number(&rest[..len]), len)
+            }
+            _ if c.is_alphabetic() || c == '_' || c == '\\' || c == '$' => {
+                let len = rest
+                    .find(|x: char| !(x.is_alphanumeric() || "_.\\$?".contains(x)))
+                    .unwrap_or(rest.len());
+                let name = &rest[..len];
+                match rest[len..].chars().next() {
+                    Some('(') => (Token::Function(name), len),
+                    Some('!') => (Token::Sheet(&rest[..len + 1]), len + 1),
+                    _ if CELL.is_match(name)
+                        && from_excel_coords(&name.to_ascii_uppercase())
+                            .is_some_and(|(row, col)| row <= 1048576 && col <= 16384) =>
+                    {
+                        (Token::Cell(name), len)
+                    }
+                    _ => (Token::Name(name), len),
+                }
+            }
+            _ if c.is_whitespace() => {
+                let len = rest.find(|x: char| !x.is_whitespace()).unwrap_or(rest.len());
+                (Token::Space(&rest[..len]), len)
+            }
+            _ => match rest.get(..2) {
+                Some("<=") | Some(">=") | Some("<>") => (Token::Operator(&rest[..2]), 2),
+                _ if "+-*/^&=<>%:,;(){}@".contains(c) => (Token::Operator(&rest[..1]), 1),
+                _ => return None,
+            },
+        };
+        re.push(token);
+        i += len;
+    }
+    Some(re)
+}
+
+/// Length of the `"` or `'` quoted text `text` starts with, where a doubled quote is an escape.
+fn quoted_len(text: &str) -> Option<usize> {
+    let quote = text.chars().next()?;
+    let mut i = 1;
+    loop {
+        i = i + text[i..].find(quote)? + 1;
+        if !text[i..].starts_with(quote) {
+            return Some(i);
+        }
+        i += 1;
+    }
+}
+
+/// Length of the structured reference `text` starts with; `'` escapes the next character.
+fn bracket_len(text: &str) -> Option<usize> {
+    let mut depth = 0;
+    let mut chars = text.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '[' => depth += 1,
+            ']' if depth == 1 => return Some(i + 1),
+            ']' => depth -= 1,
+            '\'' => {
+                chars.next();
+            }
+            _ => (),
+        }
+    }
+    None
+}
+
+/// Length of the number `text` starts with, exponent included.
+fn number_len(text: &str) -> usize {
+    let len = text
+        .find(|x: char| !(x.is_ascii_digit() || x == '.'))
+        .unwrap_or(text.len());
+    let rest = &text[len..];
+    if rest.starts_with(['E', 'e']) {
+        let sign = if rest[1..].starts_with(['+', '-']) { 2 } else { 1 };
+        let digits = rest[sign..]
+            .find(|x: char| !x.is_ascii_digit())
+            .unwrap_or(rest.len() - sign);
+        if digits > 0 {
+            return len + sign + digits;
+        }
+    }
+    len
+}
+
+/// Whether the parentheses and braces of a formula pair up, and it isn't empty.
+fn is_balanced(tokens: &[Token]) -> bool {
+    let mut open = vec![];
+    for token in tokens {
+        match token.text() {
+            "(" | "{" => open.push(token.text()),
+            ")" if open.pop() != Some("(") => return false,
+            "}" if open.pop() != Some("{") => return false,
+            _ => (),
+        }
+    }
+    open.is_empty() && tokens.iter().any(|x| !matches!(x, Token::Space(_)))
+}
+
+/// The part a cell plays in a shared formula.
+pub enum Shared {
+    /// The first cell, which stores the text for all the cells in `ref`.
+    Anchor(usize, String),
+    /// The other cells, which only refer to the anchor by `si`.
+    Member(usize),
+}
+
+/// Runs of formulas down a column that only differ by the row they are in, like `=B2*C2`,
+/// `=B3*C3`, ..., which are written as one shared formula.
+#[derive(Default)]
+pub struct SharedFormulas {
+    /// The runs of each column, by first row: `(first, last, si)`.
+    cols: HashMap<i32, Vec<(i32, i32, usize)>>,
+    count: usize,
+}
+
+/// A run being collected: its first and last row, and the formula relative to the row.
+struct Run {
+    first: i32,
+    last: i32,
+    key: String,
+}
+
+impl SharedFormulas {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Finds the runs in the rows of a sheet.
+    pub fn find<'a>(rows: Term<'a>) -> NifResult<Self> {
+        let mut re = Self::new();
+        let mut runs: HashMap<i32, Run> = HashMap::new();
+        let rows: ListIterator = rows.decode()?;
+        for (row, cells) in (1..).zip(rows) {
+            let cells: ListIterator = cells.decode()?;
+            for (col, cell) in (1..).zip(cells) {
+                let key = match formula_text(cell, row)? {
+                    Some(formula) => relative_to_row(formula.trim_start_matches('='), row),
+                    _ => None,
+                };
+                let key = match key {
+                    Some(key) => key,
+                    _ => continue,
+                };
+                if let Some(run) = runs.get_mut(&col) {
+                    if run.last == row - 1 && run.key == key {
+                        run.last = row;
+                        continue;
+                    }
+                }
+                if let Some(run) = runs.insert(col, Run { first: row, last: row, key }) {
+                    re.add(col, run);
+                }
+            }
+        }
+        let mut rest: Vec<(i32, Run)> = runs.into_iter().collect();
+        rest.sort_by_key(|x| x.0);
+        for (col, run) in rest {
+            re.add(col, run);
+        }
+        Ok(re)
+    }
+
+    fn add(&mut self, col: i32, run: Run) {
+        if run.last == run.first {
+            return;
+        }
+        let si = self.count;
+        self.count += 1;
+        self.cols.entry(col).or_default().push((run.first, run.last, si));
+    }
+
+    pub fn get(&self, row: i32, col: i32) -> Option<Shared> {
+        let runs = self.cols.get(&col)?;
+        // A column's runs are added top to bottom, so they are sorted by their first row.
+        let i = match runs.binary_search_by_key(&row, |x| x.0) {
+            Ok(i) => i,
+            Err(0) => return None,
+            Err(i) => i - 1,
+        };
+        let (first, last, si) = runs[i];
+        if row == first {
+            let range = format!("{}:{}", to_excel_coords(first, col), to_excel_coords(last, col));
+            Some(Shared::Anchor(si, range))
+        } else if row <= last {
+            Some(Shared::Member(si))
+        } else {
+            None
+        }
+    }
+}
+
+/// The text of a cell's `formula` or `formula_template`. Only the tuple is looked at; the
+/// rest of the cell is decoded, and checked, when it is written.
+fn formula_text<'a>(cell: Term<'a>, row: i32) -> NifResult<Option<String>> {
+    let term = if cell.is_list() {
+        let mut li: ListIterator = cell.decode()?;
+        match li.next() {
+            Some(term) => term,
+            _ => return Ok(None),
+        }
+    } else {
+        cell
+    };
+    if !term.is_tuple() {
+        return Ok(None);
+    }
+    let li = ::rustler::types::tuple::get_tuple(term)?;
+    if li.len() < 2 || li.len() > 3 || !li[0].is_atom() {
+        return Ok(None);
+    }
+    Ok(match li[0].atom_to_string()?.as_str() {
+        "formula" => Some(li[1].decode()?),
+        "formula_template" => Some(expand_template(&li[1].decode::<String>()?, row)?),
+        _ => None,
+    })
+}
+
+/// Rewrites the relative rows of the cell references in `formula` as offsets from `row`,
+/// so formulas that Excel would shift into one another come out the same.
+///
+/// Gives `None` for whole rows like `2:2`, which are left unshared rather than shifted, and
+/// for formulas returning dynamic arrays, which are written as array formulas instead.
+fn relative_to_row(formula: &str, row: i32) -> Option<String> {
+    let tokens = tokenize(formula)?;
+    let colon = Some(&Token::Operator(":"));
+    let mut re = String::with_capacity(formula.len() + 8);
+    for (i, token) in tokens.iter().enumerate() {
+        match token {
+            Token::Number(_) if tokens.get(i + 1) == colon || i > 0 && tokens.get(i - 1) == colon => {
+                return None;
+            }
+            Token::Function(name) if function_prefix(name).1 => return None,
+            Token::Cell(cell) => {
+                let (col, digits) = cell.split_at(cell.find(|x: char| x.is_ascii_digit())?);
+                if col.ends_with('$') {
+                    re.push_str(cell);
+                } else {
+                    let offset = digits.parse::<i32>().ok()? - row;
+                    re.push_str(&format!("{}R[{}]", col, offset));
+                }
+            }
+            _ => re.push_str(token.text()),
+        }
+    }
+    Some(re)
+}
+
+/// Moves the relative parts of the cell references in `formula` by `rows` and `cols`, as
+/// Excel does when filling the anchor of a shared formula into the rest of its range.
+/// References moved off the sheet become `#REF!`.
+pub fn shift_formula(formula: &str, rows: i32, cols: i32) -> Option<String> {
+    let tokens = tokenize(formula)?;
+    let mut re = String::with_capacity(formula.len() + 8);
+    for token in tokens.iter() {
+        match token {
+            Token::Cell(cell) => {
+                let (col, _) = cell.split_at(cell.find(|x: char| x.is_ascii_digit())?);
+                let (col_absolute, row_absolute) = (col.starts_with('$'), col.ends_with('$'));
+                let (row, col) = from_excel_coords(&cell.to_ascii_uppercase())?;
+                let row = if row_absolute { row } else { row + rows };
+                let col = if col_absolute { col } else { col + cols };
+                if !(1..=1048576).contains(&row) || !(1..=16384).contains(&col) {
+                    re.push_str("#REF!");
+                } else {
+                    if col_absolute {
+                        re.push('$');
+                    }
+                    re.push_str(&encode_col(col));
+                    if row_absolute {
+                        re.push('$');
+                    }
+                    re.push_str(&row.to_string());
+                }
+            }
+            _ => re.push_str(token.text()),
+        }
+    }
+    Some(re)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokenize_gives_back_the_formula() {
+        let formula = r#"SUM('My sheet'!$A$1:B2, "a ""b""", [@Price], #N/A, 1.5E+3)&C1#"#;
+        let tokens = tokenize(formula).unwrap();
+        assert_eq!(tokens.iter().map(|x| x.text()).collect::<String>(), formula);
+        assert_eq!(tokens[0], Token::Function("SUM"));
+        assert_eq!(tokens[2], Token::Sheet("'My sheet'!"));
+        assert_eq!(tokens[3], Token::Cell("$A$1"));
+        assert!(tokens.contains(&Token::String(r#""a ""b""""#)));
+        assert!(tokens.contains(&Token::Bracket("[@Price]")));
+        assert!(tokens.contains(&Token::Error("#N/A")));
+        assert!(tokens.contains(&Token::Number("1.5E+3")));
+        assert_eq!(tokens[tokens.len() - 1], Token::Operator("#"));
+    }
+
+    #[test]
+    fn tokenize_edge_cases() {
+        assert_eq!(tokenize("1E").unwrap(), vec![Token::Number("1"), Token::Name("E")]);
+        assert_eq!(tokenize(".5").unwrap(), vec![Token::Number(".5")]);
+        assert_eq!(tokenize("XFD1048576").unwrap(), vec![Token::Cell("XFD1048576")]);
+        assert_eq!(tokenize("XFE1").unwrap(), vec![Token::Name("XFE1")]);
+        assert_eq!(tokenize("A1048577").unwrap(), vec![Token::Name("A1048577")]);
+        assert_eq!(
+            tokenize("[[#This Row],[It''s]]").unwrap(),
+            vec![Token::Bracket("[[#This Row],[It''s]]")]
+        );
+        assert_eq!(tokenize("\"open"), None);
+        assert_eq!(tokenize("'Sheet1'"), None);
+        assert_eq!(tokenize("[Price"), None);
+        assert_eq!(tokenize("1~2"), None);
+    }
+
+    #[test]
+    fn balanced_formulas() {
+        let balanced = |x| is_balanced(&tokenize(x).unwrap());
+        assert!(balanced("SUM((A1),{1,2})"));
+        assert!(balanced("\"(\""));
+        assert!(!balanced("(1"));
+        assert!(!balanced(")("));
+        assert!(!balanced("{1,2)"));
+        assert!(!balanced(""));
+        assert!(!balanced("  "));
+    }
+
+    #[test]
+    fn templates_are_filled_in_for_the_row() {
+        assert_eq!(expand_template("A{row}*B{row - 1}+C{row+2}", 5).ok(), Some("A5*B4+C7".to_string()));
+        assert_eq!(expand_template("{ROW}", 5).ok(), Some("{ROW}".to_string()));
+        assert_eq!(expand_template("A{row-1}", 2).ok(), Some("A1".to_string()));
+        assert!(expand_template("A{row-1}", 1).is_err());
+        assert!(expand_template("A{row+1}", 1048576).is_err());
+    }
+
+    #[test]
+    fn shifting_keeps_absolute_parts() {
+        assert_eq!(shift_formula("A1+$B$2+C$3+$D4", 1, 1), Some("B2+$B$2+D$3+$D5".to_string()));
+        assert_eq!(shift_formula("Sheet1!A2:A3", -1, 0), Some("Sheet1!A1:A2".to_string()));
+        assert_eq!(shift_formula("A1+B2", -1, 0), Some("#REF!+B1".to_string()));
+        assert_eq!(shift_formula("XFD1", 0, 1), Some("#REF!".to_string()));
+    }
+}
@@ -2,20 +2,27 @@
 extern crate rustler;
 #[macro_use]
 extern crate lazy_static;
+use rustler::types::Binary;
 use rustler::{Encoder, Env, NifResult, Term};
 
+mod col_width;
+mod date;
 mod error;
+mod formula;
 mod util;
 mod wb_compiler;
+mod wb_reader;
 mod wb_writer;
 mod workbook;
+mod xml_reader;
 mod xml_templates;
 mod xml_writer;
 
 rustler_export_nifs! {
     "Elixir.Elixlsx.Native",
     [
-     ("write_excel_nif", 1, write_excel)
+     ("write_excel_nif", 1, write_excel),
+     ("read_excel_nif", 1, read_excel, rustler::SchedulerFlags::DirtyCpu)
     ],
     None
 }
@@ -28,3 +35,8 @@ fn write_excel<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
         .collect::<Vec<(Vec<u8>, String)>>()
         .encode(env))
 }
+
+fn read_excel<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let files: Vec<(String, Binary)> = args[0].decode()?;
+    wb_reader::read_excel(env, files)
+}
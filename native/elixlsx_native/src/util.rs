@@ -1,3 +1,7 @@
+use rustler::{Decoder, Error, NifResult, Term};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
 pub fn to_excel_coords(y: i32, x: i32) -> String {
     encode_col(x) + y.to_string().as_str()
 }
@@ -17,3 +21,139 @@ pub fn encode_col(col: i32) -> String {
         }
     }
 }
+
+/// Parses an A1 reference (absolute or not) into 1-based `(row, col)`.
+pub fn from_excel_coords(cell: &str) -> Option<(i32, i32)> {
+    let cell = cell.replace('$', "");
+    let split = cell.find(|c: char| c.is_ascii_digit())?;
+    let (letters, digits) = cell.split_at(split);
+    if letters.is_empty() || !letters.chars().all(|c| c.is_ascii_uppercase()) {
+        return None;
+    }
+    let col = letters
+        .chars()
+        .try_fold(0i32, |acc, c| acc.checked_mul(26)?.checked_add(c as i32 - 64))?;
+    Some((digits.parse().ok()?, col))
+}
+
+/// Turns `dark_grid` into `darkGrid`, leaving names that are already camel cased alone.
+pub fn to_camel_case(name: &str) -> String {
+    let mut re = String::with_capacity(name.len());
+    let mut upper = false;
+    for c in name.chars() {
+        if c == '_' {
+            upper = true;
+        } else if upper {
+            re.extend(c.to_uppercase());
+            upper = false;
+        } else {
+            re.push(c);
+        }
+    }
+    re
+}
+
+/// A float that can be part of a style key. Equality and hashing use the bit pattern, so
+/// that `0.0` and `-0.0` are different keys and NaN is equal to itself.
+#[derive(Default, Clone, Copy)]
+pub struct Float(pub f64);
+
+impl PartialEq for Float {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.to_bits() == other.0.to_bits()
+    }
+}
+
+impl Eq for Float {}
+
+impl Hash for Float {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.to_bits().hash(state)
+    }
+}
+
+impl fmt::Display for Float {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl<'a> Decoder<'a> for Float {
+    fn decode(term: Term<'a>) -> NifResult<Self> {
+        term.decode::<f64>()
+            .or_else(|_| term.decode::<i64>().map(|x| x as f64))
+            .map(Float)
+    }
+}
+
+/// The decimal digits of an integer too big for an `i64`, read from its external term format
+/// (`SMALL_BIG_EXT` or `LARGE_BIG_EXT`), as rustler has no big integers.
+pub fn decode_big_integer<'a>(term: Term<'a>) -> NifResult<String> {
+    if !term.is_number() {
+        return Err(Error::BadArg);
+    }
+    big_integer_digits(term.to_binary().as_slice()).ok_or(Error::BadArg)
+}
+
+fn big_integer_digits(bytes: &[u8]) -> Option<String> {
+    let (sign, magnitude) = match bytes.get(1) {
+        Some(110) if bytes.len() > 3 => (bytes[3], &bytes[4..]),
+        Some(111) if bytes.len() > 6 => (bytes[6], &bytes[7..]),
+        _ => return None,
+    };
+    // Base 256, least significant byte first, into base 10 by repeated division.
+    let mut limbs: Vec<u32> = magnitude.iter().rev().map(|&x| x as u32).collect();
+    let mut digits = vec![];
+    while limbs.iter().any(|&x| x != 0) {
+        let mut rem = 0;
+        for limb in limbs.iter_mut() {
+            let x = rem * 256 + *limb;
+            *limb = x / 10;
+            rem = x % 10;
+        }
+        digits.push((b'0' + rem as u8) as char);
+    }
+    if digits.is_empty() {
+        digits.push('0');
+    }
+    if sign != 0 {
+        digits.push('-');
+    }
+    Some(digits.into_iter().rev().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn float_equality_agrees_with_hashing() {
+        assert!(Float(0.0) != Float(-0.0));
+        assert!(Float(f64::NAN) == Float(f64::NAN));
+        let set: HashSet<Float> = [Float(0.0), Float(-0.0), Float(1.5), Float(1.5)].iter().cloned().collect();
+        assert_eq!(set.len(), 3);
+    }
+
+    #[test]
+    fn big_integers_keep_their_sign() {
+        let small_big = [131, 110, 9, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1];
+        assert_eq!(big_integer_digits(&small_big), Some("18446744073709551616".to_string()));
+        let negative = [131, 110, 9, 1, 0, 0, 0, 0, 0, 0, 0, 0, 1];
+        assert_eq!(big_integer_digits(&negative), Some("-18446744073709551616".to_string()));
+        let large_big = [131, 111, 0, 0, 0, 10, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1];
+        assert_eq!(big_integer_digits(&large_big), Some("-4722366482869645213696".to_string()));
+        // SMALL_INTEGER_EXT, which isn't a big integer.
+        assert_eq!(big_integer_digits(&[131, 97, 1]), None);
+    }
+
+    #[test]
+    fn excel_coords() {
+        assert_eq!(from_excel_coords("$B$3"), Some((3, 2)));
+        assert_eq!(from_excel_coords("XFD1048576"), Some((1048576, 16384)));
+        assert_eq!(from_excel_coords("a1"), None);
+        assert_eq!(from_excel_coords("A"), None);
+        assert_eq!(from_excel_coords("ZZZZZZZZ1"), None);
+        assert_eq!(from_excel_coords("A99999999999"), None);
+    }
+}
@@ -1,10 +1,13 @@
 use crate::error::ExcelResult;
 use rustler::types::ListIterator;
+use rustler::dynamic::{get_type, TermType};
 use rustler::{Decoder, Error, NifResult, Term};
 use std::cmp::Eq;
 use std::collections::HashMap;
 use std::hash::Hash;
-use crate::workbook::{Sheet, Workbook};
+use crate::date::is_date_format;
+use crate::util::{to_camel_case, Float};
+use crate::workbook::{decode_string, Sheet, ValueOptions, Workbook};
 
 pub fn make_workbook_comp_info<'a>(
     args: &[Term<'a>],
@@ -13,9 +16,25 @@ pub fn make_workbook_comp_info<'a>(
 
     let (sci, next_rid) = make_sheet_info(&workbook.sheets, 2);
 
+    let mut named_styles = vec![NamedStyle::builtin("Normal").unwrap()];
+    for (name, style) in &workbook.cell_styles {
+        let builtin_id = NamedStyle::builtin(name).and_then(|x| x.builtin_id);
+        let named_style = NamedStyle {
+            name: name.clone(),
+            builtin_id: builtin_id,
+            style: style.clone(),
+        };
+        match builtin_id {
+            Some(0) => named_styles[0] = named_style,
+            _ => named_styles.push(named_style),
+        }
+    }
+
     let wci = WorkbookCompInfo {
         sheet_info: sci,
+        named_styles: named_styles,
         next_free_xl_rid: next_rid,
+        values: workbook.values,
         ..Default::default()
     };
     Ok((workbook, wci))
@@ -51,13 +70,43 @@ pub struct WorkbookCompInfo {
     pub sheet_info: Vec<SheetCompInfo>,
     pub stringdb: DB<String>,
     pub fontdb: DB<Font>,
-    pub filldb: DB<String>,
+    pub filldb: DB<Fill>,
     pub cellstyledb: DB<CellStyle>,
     pub numfmtdb: DB<String>,
     pub borderstyledb: DB<BorderStyle>,
+    /// `cellStyleXfs`, so the position is the `xfId`; "Normal" is always first.
+    pub named_styles: Vec<NamedStyle>,
+    pub values: ValueOptions,
+    /// Set when a cell holds a dynamic array formula, which needs `xl/metadata.xml`.
+    pub has_dynamic_arrays: bool,
     pub next_free_xl_rid: i32,
 }
 
+impl WorkbookCompInfo {
+    /// Applies the named style a cell style refers to, adding built-in styles as they are used.
+    pub fn resolve_named_style(&mut self, style: CellStyle) -> NifResult<CellStyle> {
+        if style.named_style == "" {
+            return Ok(style);
+        }
+        let id = match self.named_styles.iter().position(|x| x.name == style.named_style) {
+            Some(id) => id,
+            _ => {
+                let named_style = NamedStyle::builtin(&style.named_style).ok_or(Error::BadArg)?;
+                self.named_styles.push(named_style);
+                self.named_styles.len() - 1
+            }
+        };
+        Ok(style.inherit(&self.named_styles[id].style))
+    }
+
+    pub fn get_named_style_id(&self, name: &str) -> i32 {
+        self.named_styles
+            .iter()
+            .position(|x| x.name == name)
+            .unwrap_or(0) as i32
+    }
+}
+
 #[derive(Default)]
 pub struct DB<T: Eq + Hash> {
     pub data: HashMap<T, i32>,
@@ -82,16 +131,185 @@ impl<T: Eq + Hash + Clone> DB<T> {
 }
 
 #[derive(Default, Eq, PartialEq, Hash, Clone)]
-pub struct Border {
+pub enum Color {
+    #[default]
+    None,
+    /// `AARRGGBB`
+    Rgb(String),
+    /// Theme color index and tint.
+    Theme(i32, Float),
+    Indexed(i32),
+}
+
+impl<'a> Decoder<'a> for Color {
+    fn decode(term: Term<'a>) -> NifResult<Self> {
+        match get_type(term) {
+            TermType::Tuple => {
+                let li = ::rustler::types::tuple::get_tuple(term)?;
+                let tag = match li.first() {
+                    Some(tag) => tag.atom_to_string()?,
+                    _ => return Err(Error::BadArg),
+                };
+                match (tag.as_str(), li.len()) {
+                    ("theme", 2) => Ok(Color::Theme(li[1].decode()?, Float(0.0))),
+                    ("theme", 3) => Ok(Color::Theme(li[1].decode()?, li[2].decode()?)),
+                    ("indexed", 2) => Ok(Color::Indexed(li[1].decode()?)),
+                    _ => Err(Error::BadArg),
+                }
+            }
+            TermType::Atom if term.atom_to_string()? == "nil" => Ok(Color::None),
+            _ => Color::parse(&decode_string(term)?).ok_or(Error::BadArg),
+        }
+    }
+}
+
+impl<'a> Color {
+    /// Parses `#RRGGBB`, `#AARRGGBB` (the `#` is optional) or a color name.
+    pub fn parse(color: &str) -> Option<Self> {
+        let hex = color.trim_start_matches('#');
+        if hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            match hex.len() {
+                6 => return Some(Color::Rgb(format!("FF{}", hex.to_uppercase()))),
+                8 => return Some(Color::Rgb(hex.to_uppercase())),
+                _ => (),
+            }
+        }
+        let rgb = match color.to_lowercase().as_str() {
+            "black" => "000000",
+            "white" => "FFFFFF",
+            "red" => "FF0000",
+            "lime" => "00FF00",
+            "blue" => "0000FF",
+            "yellow" => "FFFF00",
+            "cyan" | "aqua" => "00FFFF",
+            "magenta" | "fuchsia" => "FF00FF",
+            "silver" => "C0C0C0",
+            "gray" | "grey" => "808080",
+            "maroon" => "800000",
+            "olive" => "808000",
+            "green" => "008000",
+            "purple" => "800080",
+            "teal" => "008080",
+            "navy" => "000080",
+            "orange" => "FFA500",
+            "brown" => "A52A2A",
+            "pink" => "FFC0CB",
+            _ => return None,
+        };
+        Some(Color::Rgb(format!("FF{}", rgb)))
+    }
+
+    pub fn get_attributes(&'a self) -> Vec<(&'a ToString, &'a ToString)> {
+        match self {
+            Color::None => vec![],
+            Color::Rgb(rgb) => vec![(&"rgb", rgb)],
+            Color::Theme(theme, tint) if tint.0 != 0.0 => vec![(&"theme", theme), (&"tint", tint)],
+            Color::Theme(theme, _) => vec![(&"theme", theme)],
+            Color::Indexed(indexed) => vec![(&"indexed", indexed)],
+        }
+    }
+}
+
+#[derive(Default, Eq, PartialEq, Hash, Clone)]
+pub enum Fill {
+    #[default]
+    None,
+    Pattern {
+        pattern_type: String,
+        fg_color: Color,
+        bg_color: Color,
+    },
+    Gradient(GradientFill),
+}
+
+impl<'a> Fill {
+    const PATTERN_TYPES: [&'static str; 19] = [
+        "none", "solid", "mediumGray", "darkGray", "lightGray", "darkHorizontal", "darkVertical",
+        "darkDown", "darkUp", "darkGrid", "darkTrellis", "lightHorizontal", "lightVertical",
+        "lightDown", "lightUp", "lightGrid", "lightTrellis", "gray125", "gray0625",
+    ];
+
+    /// A `fill:` option takes precedence over the `bg_color` shorthand for a solid fill.
+    fn new(map: &HashMap<String, Term<'a>>) -> NifResult<Self> {
+        if let Some(fill) = map.get("fill") {
+            let map = crate::workbook::decode_keyword_list(fill.decode()?)?;
+            if map.contains_key("gradient") {
+                return Ok(Fill::Gradient(GradientFill::new(&map)?));
+            }
+            let pattern_type = match map.get("pattern") {
+                Some(&pattern) => to_camel_case(&decode_string(pattern)?),
+                _ => "solid".to_string(),
+            };
+            if !Fill::PATTERN_TYPES.contains(&pattern_type.as_str()) {
+                return Err(Error::BadArg);
+            }
+            Ok(Fill::Pattern {
+                pattern_type: pattern_type,
+                fg_color: get_keyword_value(&map, "fg_color", Default::default())?,
+                bg_color: get_keyword_value(&map, "bg_color", Default::default())?,
+            })
+        } else {
+            Ok(match get_keyword_value(map, "bg_color", Color::None)? {
+                Color::None => Fill::None,
+                color => Fill::Pattern {
+                    pattern_type: "solid".to_string(),
+                    fg_color: color,
+                    bg_color: Color::None,
+                },
+            })
+        }
+    }
+}
+
+#[derive(Default, Eq, PartialEq, Hash, Clone)]
+pub struct GradientFill {
+    /// `linear` or `path`
     pub type_: String,
+    pub degree: Float,
+    pub left: Float,
+    pub right: Float,
+    pub top: Float,
+    pub bottom: Float,
+    /// Positions between 0 and 1 with their colors.
+    pub stops: Vec<(Float, Color)>,
+}
+
+impl<'a> GradientFill {
+    fn new(map: &HashMap<String, Term<'a>>) -> NifResult<Self> {
+        let type_ = get_string(map, "gradient")?;
+        if type_ != "linear" && type_ != "path" {
+            return Err(Error::BadArg);
+        }
+        let stops: ListIterator = map.get("stops").ok_or(Error::BadArg)?.decode()?;
+        let stops: Vec<(Float, Color)> = stops.map(|x| x.decode()).collect::<NifResult<_>>()?;
+        if stops.is_empty()
+            || stops.iter().any(|(position, color)| {
+                !(0.0..=1.0).contains(&position.0) || *color == Color::None
+            })
+        {
+            return Err(Error::BadArg);
+        }
+        Ok(GradientFill {
+            type_,
+            degree: get_keyword_value(map, "degree", Default::default())?,
+            left: get_keyword_value(map, "left", Default::default())?,
+            right: get_keyword_value(map, "right", Default::default())?,
+            top: get_keyword_value(map, "top", Default::default())?,
+            bottom: get_keyword_value(map, "bottom", Default::default())?,
+            stops,
+        })
+    }
+}
+
+#[derive(Default, Eq, PartialEq, Hash, Clone)]
+pub struct Border {
     pub style: String,
-    pub color: String,
+    pub color: Color,
 }
 impl<'a> Border {
-    fn new(map: &HashMap<String, Term<'a>>, type_: String) -> NifResult<Self> {
+    fn new(map: &HashMap<String, Term<'a>>) -> NifResult<Self> {
         Ok(Border {
-            type_: type_,
-            style: get_keyword_value(map, "style", Default::default())?,
+            style: get_string(map, "style")?,
             color: get_keyword_value(map, "color", Default::default())?,
         })
     }
@@ -110,10 +328,17 @@ pub struct BorderStyle {
 impl<'a> BorderStyle {
     fn new(map: &HashMap<String, Term<'a>>) -> NifResult<Self> {
         fn get_border<'a>(map: &HashMap<String, Term<'a>>, name: &str) -> NifResult<Border> {
-            let li: ListIterator = map.get(name).ok_or_else(|| Error::BadArg)?.decode()?;
-            let map = crate::workbook::decode_keyword_list(li)?;
-            Border::new(&map, name.to_string())
+            let map = match map.get(name) {
+                Some(term) => crate::workbook::decode_keyword_list(term.decode()?)?,
+                _ => Default::default(),
+            };
+            Border::new(&map)
         }
+        let map = match map.get("border") {
+            Some(term) => crate::workbook::decode_keyword_list(term.decode()?)?,
+            _ => Default::default(),
+        };
+        let map = &map;
 
         Ok(BorderStyle {
             left: get_border(map, "left")?,
@@ -129,31 +354,65 @@ impl<'a> BorderStyle {
 
 #[derive(Default, Eq, PartialEq, Hash, Clone)]
 pub struct Font {
-    pub bold: bool,
-    pub italic: bool,
-    pub underline: bool,
-    pub strike: bool,
-    pub size: i32,
-    pub color: String,
-    pub wrap_text: bool,
-    pub align_horizontal: String,
-    pub align_vertical: String,
+    /// The on/off properties are `None` when not given, so that a cell can turn off what
+    /// its named style turns on.
+    pub bold: Option<bool>,
+    pub italic: Option<bool>,
+    /// `single`, `double`, `singleAccounting` or `doubleAccounting`; empty for none.
+    pub underline: String,
+    pub strike: Option<bool>,
+    pub outline: Option<bool>,
+    pub shadow: Option<bool>,
+    /// `superscript` or `subscript`; empty for baseline.
+    pub vert_align: String,
+    pub size: Float,
+    pub color: Color,
     pub font: String,
+    pub family: Option<i32>,
+    /// `major`, `minor` or `none`; empty when not given.
+    pub scheme: String,
+    pub charset: Option<i32>,
 }
 
 impl<'a> Font {
-    fn new(map: &HashMap<String, Term<'a>>) -> NifResult<Option<Self>> {
+    pub fn new(map: &HashMap<String, Term<'a>>) -> NifResult<Option<Self>> {
+        let underline = match get_string(map, "underline")?.as_str() {
+            "true" => "single".to_string(),
+            "false" | "nil" | "none" => "".to_string(),
+            other => to_camel_case(other),
+        };
+        if !["", "single", "double", "singleAccounting", "doubleAccounting"]
+            .contains(&underline.as_str())
+        {
+            return Err(Error::BadArg);
+        }
+        let mut vert_align = get_string(map, "vert_align")?;
+        if vert_align == "" && get_bool(map, "superscript") {
+            vert_align = "superscript".to_string();
+        } else if vert_align == "" && get_bool(map, "subscript") {
+            vert_align = "subscript".to_string();
+        }
+        if !["", "superscript", "subscript", "baseline"].contains(&vert_align.as_str()) {
+            return Err(Error::BadArg);
+        }
+        let scheme = get_string(map, "scheme")?;
+        if !["", "major", "minor", "none"].contains(&scheme.as_str()) {
+            return Err(Error::BadArg);
+        }
         let re = Font {
-            bold: get_bool(map, "bold"),
-            italic: get_bool(map, "italic"),
-            underline: get_bool(map, "underline"),
-            strike: get_bool(map, "strike"),
-            size: map.get("size").map_or(Ok(0), |x| x.decode())?,
+            bold: get_optional_bool(map, "bold"),
+            italic: get_optional_bool(map, "italic"),
+            underline: underline,
+            strike: get_optional_bool(map, "strike"),
+            outline: get_optional_bool(map, "outline"),
+            shadow: get_optional_bool(map, "shadow"),
+            vert_align: vert_align,
+            size: get_keyword_value(map, "size", Default::default())?,
             color: get_keyword_value(map, "color", Default::default())?,
-            wrap_text: get_bool(map, "wrap_text"),
-            align_horizontal: get_keyword_value(map, "align_horizontal", Default::default())?,
-            align_vertical: get_keyword_value(map, "align_vertical", Default::default())?,
             font: get_keyword_value(map, "font", Default::default())?,
+            family: map.get("family").map(|x| x.decode()).transpose()?,
+            scheme: scheme,
+            charset: map.get("charset").map(|x| x.decode()).transpose()?,
         };
         if re == Default::default() {
             Ok(None)
@@ -162,16 +421,147 @@ impl<'a> Font {
         }
     }
 
-    pub fn get_alignment_attributes(&'a self) -> Vec<(&'a ToString, &'a ToString)> {
+    /// The workbook's default font (index 0), Calibri 11 unless overridden.
+    pub fn new_default(font: Option<Font>) -> Self {
+        let mut font = font.unwrap_or_default();
+        if font.font == "" {
+            font.font = "Calibri".to_string();
+            font.family = font.family.or(Some(2));
+            if font.scheme == "" {
+                font.scheme = "minor".to_string();
+            }
+        }
+        if font.size.0 <= 0.0 {
+            font.size = Float(11.0);
+        }
+        font
+    }
+
+    /// Takes the name and size this font leaves out from the default font, so that
+    /// e.g. a bold cell keeps the workbook's typeface.
+    pub fn inherit(&self, default: &Font) -> Font {
+        let mut font = self.clone();
+        if font.font == "" {
+            font.font = default.font.clone();
+            font.family = font.family.or(default.family);
+            if font.scheme == "" {
+                font.scheme = default.scheme.clone();
+            }
+            font.charset = font.charset.or(default.charset);
+        }
+        if font.size.0 <= 0.0 {
+            font.size = default.size;
+        }
+        font
+    }
+
+    /// This font over `parent`, property by property: whatever this font sets wins, and
+    /// the rest comes from `parent`, so an italic cell in a bold style is bold and italic,
+    /// while `bold: false` makes it regular.
+    pub fn merge(&self, parent: &Font) -> Font {
+        let mut font = self.inherit(parent);
+        font.bold = self.bold.or(parent.bold);
+        font.italic = self.italic.or(parent.italic);
+        font.strike = self.strike.or(parent.strike);
+        font.outline = self.outline.or(parent.outline);
+        font.shadow = self.shadow.or(parent.shadow);
+        if font.underline == "" {
+            font.underline = parent.underline.clone();
+        }
+        if font.vert_align == "" {
+            font.vert_align = parent.vert_align.clone();
+        }
+        if font.color == Color::None {
+            font.color = parent.color.clone();
+        }
+        font
+    }
+}
+
+#[derive(Default, Eq, PartialEq, Hash, Clone)]
+pub struct Alignment {
+    pub horizontal: String,
+    pub vertical: String,
+    pub wrap_text: bool,
+    /// 0-90 counterclockwise, 91-180 clockwise, 255 for vertically stacked text.
+    pub text_rotation: Option<i32>,
+    pub indent: Option<i32>,
+    pub shrink_to_fit: bool,
+    pub justify_last_line: bool,
+    /// 0 context dependent, 1 left-to-right, 2 right-to-left.
+    pub reading_order: Option<i32>,
+}
+
+impl<'a> Alignment {
+    const HORIZONTAL: [&'static str; 8] = [
+        "general", "left", "center", "right", "fill", "justify", "centerContinuous", "distributed",
+    ];
+    const VERTICAL: [&'static str; 5] = ["top", "center", "bottom", "justify", "distributed"];
+
+    fn new(map: &HashMap<String, Term<'a>>) -> NifResult<Self> {
+        let horizontal = to_camel_case(&get_string(map, "align_horizontal")?);
+        let vertical = to_camel_case(&get_string(map, "align_vertical")?);
+        if (horizontal != "" && !Alignment::HORIZONTAL.contains(&horizontal.as_str()))
+            || (vertical != "" && !Alignment::VERTICAL.contains(&vertical.as_str()))
+        {
+            return Err(Error::BadArg);
+        }
+        let text_rotation = match map.get("text_rotation") {
+            Some(&term) if term.is_atom() => match decode_string(term)?.as_str() {
+                "vertical" => Some(255),
+                _ => return Err(Error::BadArg),
+            },
+            Some(term) => match term.decode::<i32>()? {
+                x @ 0..=180 => Some(x),
+                x @ -90..=-1 => Some(90 - x),
+                _ => return Err(Error::BadArg),
+            },
+            _ => None,
+        };
+        let reading_order = match get_string(map, "reading_order")?.as_str() {
+            "" => None,
+            "context" => Some(0),
+            "left_to_right" | "ltr" => Some(1),
+            "right_to_left" | "rtl" => Some(2),
+            _ => return Err(Error::BadArg),
+        };
+        Ok(Alignment {
+            horizontal: horizontal,
+            vertical: vertical,
+            wrap_text: get_bool(map, "wrap_text"),
+            text_rotation: text_rotation,
+            indent: map.get("indent").map(|x| x.decode()).transpose()?,
+            shrink_to_fit: get_bool(map, "shrink_to_fit"),
+            justify_last_line: get_bool(map, "justify_last_line"),
+            reading_order: reading_order,
+        })
+    }
+
+    pub fn get_attributes(&'a self) -> Vec<(&'a ToString, &'a ToString)> {
         let mut re: Vec<(&'a ToString, &'a ToString)> = vec![];
+        if self.horizontal != "" {
+            re.push((&"horizontal", &self.horizontal));
+        }
+        if self.vertical != "" {
+            re.push((&"vertical", &self.vertical));
+        }
+        if let Some(text_rotation) = &self.text_rotation {
+            re.push((&"textRotation", text_rotation));
+        }
         if self.wrap_text {
             re.push((&"wrapText", &"1"));
         }
-        if self.align_horizontal != "" {
-            re.push((&"horizontal", &self.align_horizontal));
+        if let Some(indent) = &self.indent {
+            re.push((&"indent", indent));
+        }
+        if self.justify_last_line {
+            re.push((&"justifyLastLine", &"1"));
         }
-        if self.align_vertical != "" {
-            re.push((&"vertical", &self.align_vertical));
+        if self.shrink_to_fit {
+            re.push((&"shrinkToFit", &"1"));
+        }
+        if let Some(reading_order) = &self.reading_order {
+            re.push((&"readingOrder", reading_order));
         }
         re
     }
@@ -180,9 +570,12 @@ impl<'a> Font {
 #[derive(Default, Eq, PartialEq, Hash, Clone)]
 pub struct CellStyle {
     pub font: Option<Font>,
-    pub fill: String,
-    pub numfmt: String,
+    pub fill: Fill,
+    pub numfmt: NumFmt,
     pub border: BorderStyle,
+    pub alignment: Alignment,
+    /// Name of the named style (`cellStyleXfs` entry) this style is based on.
+    pub named_style: String,
 }
 
 impl<'a> CellStyle {
@@ -190,25 +583,353 @@ impl<'a> CellStyle {
         let map = crate::workbook::decode_keyword_list(list)?;
         Ok(CellStyle {
             font: Font::new(&map)?,
-            fill: get_keyword_value(&map, "bg_color", Default::default())?,
+            fill: Fill::new(&map)?,
             numfmt: get_numfmt(&map)?,
             border: BorderStyle::new(&map)?,
+            alignment: Alignment::new(&map)?,
+            named_style: get_string(&map, "cell_style")?,
         })
     }
+
+    /// Takes every part (font, fill, ...) this style doesn't set from `parent`; fonts are
+    /// merged property by property.
+    pub fn inherit(&self, parent: &CellStyle) -> CellStyle {
+        CellStyle {
+            font: match (&self.font, &parent.font) {
+                (Some(font), Some(parent)) => Some(font.merge(parent)),
+                (font, parent) => font.clone().or_else(|| parent.clone()),
+            },
+            fill: match self.fill {
+                Fill::None => parent.fill.clone(),
+                _ => self.fill.clone(),
+            },
+            numfmt: match self.numfmt {
+                NumFmt::BuiltIn(0) => parent.numfmt.clone(),
+                _ => self.numfmt.clone(),
+            },
+            border: if self.border == Default::default() {
+                parent.border.clone()
+            } else {
+                self.border.clone()
+            },
+            alignment: if self.alignment == Default::default() {
+                parent.alignment.clone()
+            } else {
+                self.alignment.clone()
+            },
+            named_style: if self.named_style == "" {
+                parent.named_style.clone()
+            } else {
+                self.named_style.clone()
+            },
+        }
+    }
     pub fn is_date(&self) -> bool {
-        self.numfmt.contains("yy")
+        match &self.numfmt {
+            NumFmt::BuiltIn(id) => NumFmt::BUILT_IN_DATES.iter().any(|x| x.contains(id)),
+            NumFmt::Custom(fmt) => is_date_format(fmt),
+        }
+    }
+}
+
+#[derive(Eq, PartialEq, Hash, Clone)]
+pub enum NumFmt {
+    /// One of the formats Excel has built in (ids below 164), 0 being General.
+    BuiltIn(i32),
+    Custom(String),
+}
+
+impl Default for NumFmt {
+    fn default() -> Self {
+        NumFmt::BuiltIn(0)
     }
 }
 
-fn get_numfmt<'a>(map: &HashMap<String, Term<'a>>) -> NifResult<String> {
+impl NumFmt {
+    const BUILT_IN_DATES: [::std::ops::RangeInclusive<i32>; 4] =
+        [14..=22, 27..=36, 45..=47, 50..=58];
+
+    /// Excel's built-in formats by code, including en-US spellings of the same id.
+    const BUILT_IN: &'static [(&'static str, i32)] = &[
+        ("General", 0),
+        ("general", 0),
+        ("0", 1),
+        ("0.00", 2),
+        ("#,##0", 3),
+        ("#,##0.00", 4),
+        ("\"$\"#,##0_);\\(\"$\"#,##0\\)", 5),
+        ("$#,##0_);($#,##0)", 5),
+        ("\"$\"#,##0_);[Red]\\(\"$\"#,##0\\)", 6),
+        ("$#,##0_);[Red]($#,##0)", 6),
+        ("\"$\"#,##0.00_);\\(\"$\"#,##0.00\\)", 7),
+        ("$#,##0.00_);($#,##0.00)", 7),
+        ("\"$\"#,##0.00_);[Red]\\(\"$\"#,##0.00\\)", 8),
+        ("$#,##0.00_);[Red]($#,##0.00)", 8),
+        ("0%", 9),
+        ("0.00%", 10),
+        ("0.00E+00", 11),
+        ("# ?/?", 12),
+        ("# ??/??", 13),
+        ("mm-dd-yy", 14),
+        ("m/d/yyyy", 14),
+        ("d-mmm-yy", 15),
+        ("d-mmm", 16),
+        ("mmm-yy", 17),
+        ("h:mm AM/PM", 18),
+        ("h:mm:ss AM/PM", 19),
+        ("h:mm", 20),
+        ("h:mm:ss", 21),
+        ("m/d/yy h:mm", 22),
+        ("m/d/yyyy h:mm", 22),
+        ("#,##0 ;(#,##0)", 37),
+        ("#,##0_);(#,##0)", 37),
+        ("#,##0 ;[Red](#,##0)", 38),
+        ("#,##0_);[Red](#,##0)", 38),
+        ("#,##0.00;(#,##0.00)", 39),
+        ("#,##0.00_);(#,##0.00)", 39),
+        ("#,##0.00;[Red](#,##0.00)", 40),
+        ("#,##0.00_);[Red](#,##0.00)", 40),
+        ("_(* #,##0_);_(* \\(#,##0\\);_(* \"-\"_);_(@_)", 41),
+        ("_(\"$\"* #,##0_);_(\"$\"* \\(#,##0\\);_(\"$\"* \"-\"_);_(@_)", 42),
+        ("_(* #,##0.00_);_(* \\(#,##0.00\\);_(* \"-\"??_);_(@_)", 43),
+        ("_(\"$\"* #,##0.00_);_(\"$\"* \\(#,##0.00\\);_(\"$\"* \"-\"??_);_(@_)", 44),
+        ("mm:ss", 45),
+        ("[h]:mm:ss", 46),
+        ("mmss.0", 47),
+        ("##0.0E+0", 48),
+        ("@", 49),
+    ];
+
+    /// Reuses the reserved id when `fmt` is one of Excel's built-in formats.
+    pub fn from_format_code(fmt: String) -> Self {
+        lazy_static! {
+            static ref BUILT_IN: HashMap<&'static str, i32> =
+                NumFmt::BUILT_IN.iter().cloned().collect();
+        }
+        match BUILT_IN.get(fmt.as_str()) {
+            Some(&id) => NumFmt::BuiltIn(id),
+            _ => NumFmt::Custom(fmt),
+        }
+    }
+
+    /// Whether Excel has a format reserved for `id`, either a documented one or one of the
+    /// locale-dependent date formats.
+    pub fn is_built_in(id: i32) -> bool {
+        NumFmt::BUILT_IN.iter().any(|x| x.1 == id)
+            || NumFmt::BUILT_IN_DATES.iter().any(|x| x.contains(&id))
+    }
+
+    /// The format code, where Excel documents one for a built-in id.
+    pub fn format_code(&self) -> Option<&str> {
+        match self {
+            NumFmt::BuiltIn(id) => NumFmt::BUILT_IN.iter().find(|x| x.1 == *id).map(|x| x.0),
+            NumFmt::Custom(fmt) => Some(fmt),
+        }
+    }
+}
+
+pub struct NamedStyle {
+    pub name: String,
+    /// Set for the styles Excel knows by id, so they show up localised in the Styles gallery.
+    pub builtin_id: Option<i32>,
+    pub style: CellStyle,
+}
+
+impl NamedStyle {
+    /// The formatting of Excel's built-in styles (Office 2007 theme colors).
+    pub fn builtin(name: &str) -> Option<Self> {
+        fn font(bold: bool, size: f64, color: &str) -> Option<Font> {
+            Some(Font {
+                bold: Some(bold),
+                size: Float(size),
+                color: Color::parse(color).unwrap(),
+                ..Default::default()
+            })
+        }
+        fn solid(color: &str) -> Fill {
+            Fill::Pattern {
+                pattern_type: "solid".to_string(),
+                fg_color: Color::parse(color).unwrap(),
+                bg_color: Color::None,
+            }
+        }
+        fn border(style: &str, color: &str) -> Border {
+            Border {
+                style: style.to_string(),
+                color: Color::parse(color).unwrap(),
+            }
+        }
+        fn bottom(style: &str, color: &str) -> BorderStyle {
+            BorderStyle {
+                bottom: border(style, color),
+                ..Default::default()
+            }
+        }
+        fn around(style: &str, color: &str) -> BorderStyle {
+            BorderStyle {
+                left: border(style, color),
+                right: border(style, color),
+                top: border(style, color),
+                bottom: border(style, color),
+                ..Default::default()
+            }
+        }
+        fn numfmt(id: i32) -> NumFmt {
+            NumFmt::BuiltIn(id)
+        }
+
+        let d = CellStyle::default;
+        let (id, style) = match name {
+            "Normal" => (0, d()),
+            "Comma" => (3, CellStyle { numfmt: numfmt(43), ..d() }),
+            "Currency" => (4, CellStyle { numfmt: numfmt(44), ..d() }),
+            "Percent" => (5, CellStyle { numfmt: numfmt(9), ..d() }),
+            "Comma [0]" => (6, CellStyle { numfmt: numfmt(41), ..d() }),
+            "Currency [0]" => (7, CellStyle { numfmt: numfmt(42), ..d() }),
+            "Hyperlink" => (
+                8,
+                CellStyle {
+                    font: Some(Font {
+                        underline: "single".to_string(),
+                        color: Color::parse("#0000FF").unwrap(),
+                        ..Default::default()
+                    }),
+                    ..d()
+                },
+            ),
+            "Note" => (
+                10,
+                CellStyle { fill: solid("#FFFFCC"), border: around("thin", "#B2B2B2"), ..d() },
+            ),
+            "Warning Text" => (11, CellStyle { font: font(false, 0.0, "#FF0000"), ..d() }),
+            "Title" => (15, CellStyle { font: font(true, 18.0, "#1F497D"), ..d() }),
+            "Heading 1" => (
+                16,
+                CellStyle {
+                    font: font(true, 15.0, "#1F497D"),
+                    border: bottom("thick", "#4F81BD"),
+                    ..d()
+                },
+            ),
+            "Heading 2" => (
+                17,
+                CellStyle {
+                    font: font(true, 13.0, "#1F497D"),
+                    border: bottom("thick", "#A7BFDE"),
+                    ..d()
+                },
+            ),
+            "Heading 3" => (
+                18,
+                CellStyle {
+                    font: font(true, 11.0, "#1F497D"),
+                    border: bottom("medium", "#95B3D7"),
+                    ..d()
+                },
+            ),
+            "Heading 4" => (19, CellStyle { font: font(true, 11.0, "#1F497D"), ..d() }),
+            "Input" => (
+                20,
+                CellStyle {
+                    font: font(false, 0.0, "#3F3F76"),
+                    fill: solid("#FFCC99"),
+                    border: around("thin", "#7F7F7F"),
+                    ..d()
+                },
+            ),
+            "Output" => (
+                21,
+                CellStyle {
+                    font: font(true, 0.0, "#3F3F3F"),
+                    fill: solid("#F2F2F2"),
+                    border: around("thin", "#3F3F3F"),
+                    ..d()
+                },
+            ),
+            "Calculation" => (
+                22,
+                CellStyle {
+                    font: font(true, 0.0, "#FA7D00"),
+                    fill: solid("#F2F2F2"),
+                    border: around("thin", "#7F7F7F"),
+                    ..d()
+                },
+            ),
+            "Check Cell" => (
+                23,
+                CellStyle {
+                    font: font(true, 0.0, "#FFFFFF"),
+                    fill: solid("#A5A5A5"),
+                    border: around("double", "#3F3F3F"),
+                    ..d()
+                },
+            ),
+            "Linked Cell" => (
+                24,
+                CellStyle {
+                    font: font(false, 0.0, "#FA7D00"),
+                    border: bottom("double", "#FF8001"),
+                    ..d()
+                },
+            ),
+            "Total" => (
+                25,
+                CellStyle {
+                    font: font(true, 0.0, "#000000"),
+                    border: BorderStyle {
+                        top: border("thin", "#4F81BD"),
+                        bottom: border("double", "#4F81BD"),
+                        ..Default::default()
+                    },
+                    ..d()
+                },
+            ),
+            "Good" => (
+                26,
+                CellStyle { font: font(false, 0.0, "#006100"), fill: solid("#C6EFCE"), ..d() },
+            ),
+            "Bad" => (
+                27,
+                CellStyle { font: font(false, 0.0, "#9C0006"), fill: solid("#FFC7CE"), ..d() },
+            ),
+            "Neutral" => (
+                28,
+                CellStyle { font: font(false, 0.0, "#9C6500"), fill: solid("#FFEB9C"), ..d() },
+            ),
+            "Explanatory Text" => (
+                53,
+                CellStyle {
+                    font: Some(Font {
+                        italic: Some(true),
+                        color: Color::parse("#7F7F7F").unwrap(),
+                        ..Default::default()
+                    }),
+                    ..d()
+                },
+            ),
+            _ => return None,
+        };
+        Some(NamedStyle {
+            name: name.to_string(),
+            builtin_id: Some(id),
+            style: style,
+        })
+    }
+}
+
+fn get_numfmt<'a>(map: &HashMap<String, Term<'a>>) -> NifResult<NumFmt> {
     Ok(if map.contains_key("yyyymmdd") {
-        "yyyy-mm-dd".to_string()
+        NumFmt::Custom("yyyy-mm-dd".to_string())
     } else if map.contains_key("datetime") {
-        "yyyy-mm-dd h:mm:ss".to_string()
+        NumFmt::Custom("yyyy-mm-dd h:mm:ss".to_string())
     } else if let Some(num_format) = map.get("num_format") {
-        num_format.decode()?
+        match num_format.decode::<i32>() {
+            Ok(id) if NumFmt::is_built_in(id) => NumFmt::BuiltIn(id),
+            Ok(_) => return Err(Error::BadArg),
+            _ => NumFmt::from_format_code(num_format.decode()?),
+        }
     } else {
-        "".to_string()
+        Default::default()
     })
 }
 
@@ -220,7 +941,16 @@ fn get_keyword_value<'a, T: Default + Decoder<'a>>(
     map.get(key).map_or(Ok(default), |term| term.decode())
 }
 
+fn get_string<'a>(map: &HashMap<String, Term<'a>>, key: &str) -> NifResult<String> {
+    map.get(key).map_or(Ok(Default::default()), |&term| decode_string(term))
+}
+
 fn get_bool<'a>(map: &HashMap<String, Term<'a>>, key: &str) -> bool {
     map.get(key)
         .map_or(false, |x| x.decode::<bool>().unwrap_or(false))
 }
+
+/// Like `get_bool`, but tells a missing `key` apart from an explicit `false`.
+fn get_optional_bool<'a>(map: &HashMap<String, Term<'a>>, key: &str) -> Option<bool> {
+    map.get(key).map(|x| x.decode::<bool>().unwrap_or(false))
+}
@@ -0,0 +1,929 @@
+use crate::formula::shift_formula;
+use crate::util::from_excel_coords;
+use crate::xml_reader::{unescape_text, Event, Tag, XmlReader};
+use rustler::types::atom::{nil, Atom};
+use rustler::types::map::map_new;
+use rustler::types::tuple::make_tuple;
+use rustler::types::Binary;
+use rustler::{Encoder, Env, Error, NifResult, Term};
+use std::collections::HashMap;
+
+/// The size of a worksheet; cells outside of it make the package invalid.
+const MAX_ROWS: usize = 1_048_576;
+const MAX_COLS: i64 = 16_384;
+
+/// A term read from the package, built up before it is encoded.
+#[derive(Clone, Debug, PartialEq)]
+enum Value {
+    Nil,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+    Atom(String),
+    List(Vec<Value>),
+    Tuple(Vec<Value>),
+    Map(Vec<(Value, Value)>),
+}
+
+impl Value {
+    fn to_term<'a>(&self, env: Env<'a>) -> NifResult<Term<'a>> {
+        let to_terms = |li: &Vec<Value>| {
+            li.iter()
+                .map(|x| x.to_term(env))
+                .collect::<NifResult<Vec<_>>>()
+        };
+        Ok(match self {
+            Value::Nil => nil().encode(env),
+            Value::Bool(x) => x.encode(env),
+            Value::Int(x) => x.encode(env),
+            Value::Float(x) => x.encode(env),
+            Value::String(x) => x.encode(env),
+            Value::Atom(x) => Atom::from_str(env, x)?.encode(env),
+            Value::List(li) => to_terms(li)?.encode(env),
+            Value::Tuple(li) => make_tuple(env, &to_terms(li)?),
+            Value::Map(li) => {
+                let mut map = map_new(env);
+                for (k, v) in li {
+                    map = map.map_put(k.to_term(env)?, v.to_term(env)?)?;
+                }
+                map
+            }
+        })
+    }
+}
+
+fn atom(name: &str) -> Value {
+    Value::Atom(name.to_string())
+}
+
+fn keywords(list: Vec<(&'static str, Value)>) -> Value {
+    Value::List(
+        list.into_iter()
+            .map(|(k, v)| Value::Tuple(vec![atom(k), v]))
+            .collect(),
+    )
+}
+
+fn fields(list: Vec<(&'static str, Value)>) -> Value {
+    Value::Map(list.into_iter().map(|(k, v)| (atom(k), v)).collect())
+}
+
+/// The enumerated style values handed back as atoms, snake cased; any others stay strings.
+/// Kept to a fixed list so that reading a file can't fill the atom table.
+const NAMES: [&str; 51] = [
+    "none",
+    "solid",
+    "medium_gray",
+    "dark_gray",
+    "light_gray",
+    "dark_horizontal",
+    "dark_vertical",
+    "dark_down",
+    "dark_up",
+    "dark_grid",
+    "dark_trellis",
+    "light_horizontal",
+    "light_vertical",
+    "light_down",
+    "light_up",
+    "light_grid",
+    "light_trellis",
+    "gray125",
+    "gray0625",
+    "thin",
+    "medium",
+    "dashed",
+    "dotted",
+    "thick",
+    "double",
+    "hair",
+    "medium_dashed",
+    "dash_dot",
+    "medium_dash_dot",
+    "dash_dot_dot",
+    "medium_dash_dot_dot",
+    "slant_dash_dot",
+    "general",
+    "left",
+    "center",
+    "right",
+    "fill",
+    "justify",
+    "center_continuous",
+    "distributed",
+    "top",
+    "bottom",
+    "single",
+    "single_accounting",
+    "double_accounting",
+    "superscript",
+    "subscript",
+    "baseline",
+    "major",
+    "minor",
+    "path",
+];
+
+fn name_value(name: &str) -> Value {
+    let mut snake = String::with_capacity(name.len() + 4);
+    for c in name.chars() {
+        if c.is_ascii_uppercase() {
+            snake.push('_');
+        }
+        snake.push(c.to_ascii_lowercase());
+    }
+    if NAMES.contains(&snake.as_str()) {
+        Value::Atom(snake)
+    } else {
+        Value::String(name.to_string())
+    }
+}
+
+/// Reads the unzipped files of an `.xlsx` package into the fields of an `%Elixlsx.Workbook{}`,
+/// with its sheets as the fields of `%Elixlsx.Sheet{}`s, in the shape `Workbook` and `Sheet`
+/// decode them, so that what is read can be written back.
+pub fn read_excel<'a>(env: Env<'a>, files: Vec<(String, Binary<'a>)>) -> NifResult<Term<'a>> {
+    let package = Package {
+        files: files
+            .iter()
+            .map(|(name, data)| (name.clone(), data.as_slice()))
+            .collect(),
+    };
+    let workbook_path = related(&package.relationships("")?, "officeDocument")
+        .unwrap_or_else(|| "xl/workbook.xml".to_string());
+    let rels = package.relationships(&workbook_path)?;
+    let strings = match related(&rels, "sharedStrings") {
+        Some(path) => read_shared_strings(package.xml(&path)?.unwrap_or(""))?,
+        _ => vec![],
+    };
+    let styles = match related(&rels, "styles") {
+        Some(path) => Styles::new(package.xml(&path)?.unwrap_or(""))?,
+        _ => Styles::new("")?,
+    };
+
+    let mut date1904 = false;
+    let mut active_sheet = None;
+    let mut first_visible_tab = None;
+    let mut sheets = vec![];
+    for event in XmlReader::new(package.xml(&workbook_path)?.ok_or(Error::BadArg)?) {
+        if let Event::Start(tag, _) = event? {
+            match tag.name {
+                "workbookPr" => date1904 = tag.attr_bool("date1904").unwrap_or(false),
+                "workbookView" if active_sheet.is_none() => {
+                    active_sheet = Some(tag.attr_parse::<i64>("activeTab").unwrap_or(0));
+                    first_visible_tab = Some(tag.attr_parse::<i64>("firstSheet").unwrap_or(0));
+                }
+                "sheet" => {
+                    let path = tag
+                        .attr("id")
+                        .and_then(|id| rels.get(id))
+                        .ok_or(Error::BadArg)?;
+                    let sheet = SheetReader {
+                        strings: &strings,
+                        styles: &styles,
+                        shared: HashMap::new(),
+                    }
+                    .read(package.xml(&path.1)?.unwrap_or(""), &tag)?;
+                    sheets.push(sheet);
+                }
+                _ => (),
+            }
+        }
+    }
+
+    let count = sheets.len() as i64;
+    let mut workbook = vec![
+        ("sheets", Value::List(sheets)),
+        ("date1904", Value::Bool(date1904)),
+    ];
+    if !styles.default_font.is_empty() {
+        workbook.push(("default_font", keywords(styles.default_font.clone())));
+    }
+    if let Some(index) = active_sheet.filter(|&x| x >= 0 && x < count) {
+        workbook.push(("active_sheet", Value::Int(index)));
+    }
+    if let Some(index) = first_visible_tab.filter(|&x| x >= 0 && x < count) {
+        workbook.push(("first_visible_tab", Value::Int(index)));
+    }
+    fields(workbook).to_term(env)
+}
+
+/// The files of the package, by their paths in the zip.
+struct Package<'a> {
+    files: HashMap<String, &'a [u8]>,
+}
+
+impl<'a> Package<'a> {
+    fn xml(&self, path: &str) -> NifResult<Option<&'a str>> {
+        match self.files.get(path) {
+            Some(data) => ::std::str::from_utf8(data)
+                .map(Some)
+                .map_err(|_| Error::BadArg),
+            _ => Ok(None),
+        }
+    }
+
+    /// The relationships of `part` (`""` for the package's own), from ids to their types
+    /// and the paths of the parts they point to.
+    fn relationships(&self, part: &str) -> NifResult<HashMap<String, (String, String)>> {
+        let (dir, file) = part.split_at(part.rfind('/').map_or(0, |i| i + 1));
+        let mut re = HashMap::new();
+        if let Some(xml) = self.xml(&format!("{}_rels/{}.rels", dir, file))? {
+            for event in XmlReader::new(xml) {
+                if let Event::Start(tag, _) = event? {
+                    if tag.name != "Relationship" || tag.attr("TargetMode") == Some("External") {
+                        continue;
+                    }
+                    if let (Some(id), Some(type_), Some(target)) =
+                        (tag.attr("Id"), tag.attr("Type"), tag.attr("Target"))
+                    {
+                        re.insert(
+                            id.to_string(),
+                            (type_.to_string(), resolve_path(dir, target)),
+                        );
+                    }
+                }
+            }
+        }
+        Ok(re)
+    }
+}
+
+/// The path of the first part of the relationship type ending in `/kind`.
+fn related(rels: &HashMap<String, (String, String)>, kind: &str) -> Option<String> {
+    let suffix = format!("/{}", kind);
+    rels.values()
+        .find(|x| x.0.ends_with(&suffix))
+        .map(|x| x.1.clone())
+}
+
+/// Resolves a relationship's target against the folder of the part it belongs to.
+fn resolve_path(dir: &str, target: &str) -> String {
+    let mut parts: Vec<&str> = if target.starts_with('/') {
+        vec![]
+    } else {
+        dir.split('/').filter(|x| !x.is_empty()).collect()
+    };
+    for part in target.split('/') {
+        match part {
+            "" | "." => (),
+            ".." => {
+                parts.pop();
+            }
+            _ => parts.push(part),
+        }
+    }
+    parts.join("/")
+}
+
+/// The texts of `sharedStrings.xml`.
+fn read_shared_strings(xml: &str) -> NifResult<Vec<String>> {
+    let mut re = vec![];
+    let mut reader = XmlReader::new(xml);
+    while let Some(event) = reader.next() {
+        match event? {
+            Event::Start(ref tag, true) if tag.name == "si" => re.push("".to_string()),
+            Event::Start(ref tag, false) if tag.name == "si" => {
+                re.push(read_rich_text(&mut reader, "si")?)
+            }
+            _ => (),
+        }
+    }
+    Ok(re)
+}
+
+/// The text of the `<si>` or `<is>` just started: its runs joined, phonetic hints left out.
+fn read_rich_text(reader: &mut XmlReader, name: &str) -> NifResult<String> {
+    let mut re = String::new();
+    while let Some(event) = reader.next() {
+        match event? {
+            Event::Start(ref tag, false) if tag.name == "t" => re.push_str(&reader.read_text("t")?),
+            Event::Start(ref tag, false) if tag.name == "rPh" => reader.skip("rPh")?,
+            Event::End(end) if end == name => return Ok(unescape_text(re)),
+            _ => (),
+        }
+    }
+    Err(Error::BadArg)
+}
+
+type Options = Vec<(&'static str, Value)>;
+
+/// The cell formats of `styles.xml`, as the cell options that give them back.
+struct Styles {
+    /// Options of each `cellXfs` entry, by the cells' `s` attribute.
+    xfs: Vec<Options>,
+    /// The font of the Normal style, which the workbook's `default_font` is.
+    default_font: Options,
+}
+
+impl Styles {
+    fn new(xml: &str) -> NifResult<Self> {
+        let mut num_fmts = HashMap::new();
+        let mut fonts: Vec<Options> = vec![];
+        let mut fills: Vec<Options> = vec![];
+        let mut borders: Vec<Options> = vec![];
+        let mut xfs = vec![];
+        // The collection being read, and the entry of it that isn't finished yet.
+        let mut section = "";
+        let mut entry: Options = vec![];
+        let mut side: Option<(&'static str, Options)> = None;
+        let mut fill: (String, Option<Value>, Option<Value>) = Default::default();
+
+        for event in XmlReader::new(xml) {
+            let (tag, empty) = match event? {
+                Event::Start(tag, empty) => (tag, empty),
+                Event::End(name) => {
+                    match (section, name) {
+                        (_, "numFmts")
+                        | (_, "fonts")
+                        | (_, "fills")
+                        | (_, "borders")
+                        | (_, "cellStyleXfs")
+                        | (_, "cellXfs")
+                        | (_, "dxfs") => section = "",
+                        ("fonts", "font") => fonts.push(entry.split_off(0)),
+                        ("fills", "fill") => fills.push(Styles::fill(&mut fill)),
+                        ("borders", "border") => borders.push(entry.split_off(0)),
+                        ("borders", "left")
+                        | ("borders", "right")
+                        | ("borders", "top")
+                        | ("borders", "bottom")
+                        | ("borders", "diagonal")
+                        | ("borders", "start")
+                        | ("borders", "end") => {
+                            if let Some((name, options)) = side.take() {
+                                entry.push((name, keywords(options)));
+                            }
+                        }
+                        ("cellXfs", "xf") => xfs.push(entry.split_off(0)),
+                        _ => (),
+                    }
+                    continue;
+                }
+                _ => continue,
+            };
+            match (section, tag.name) {
+                (_, "numFmts")
+                | (_, "fonts")
+                | (_, "fills")
+                | (_, "borders")
+                | (_, "cellStyleXfs")
+                | (_, "cellXfs")
+                | (_, "dxfs")
+                    if !empty =>
+                {
+                    section = tag.name
+                }
+                ("numFmts", "numFmt") => {
+                    if let (Some(id), Some(code)) =
+                        (tag.attr_parse::<i64>("numFmtId"), tag.attr("formatCode"))
+                    {
+                        num_fmts.insert(id, code.to_string());
+                    }
+                }
+                ("fonts", "font") if empty => fonts.push(vec![]),
+                ("fonts", _) => {
+                    if let Some(option) = Styles::font_option(&tag) {
+                        entry.push(option);
+                    }
+                }
+                ("fills", "fill") if empty => fills.push(vec![]),
+                ("fills", "patternFill") => {
+                    fill.0 = tag.attr("patternType").unwrap_or("none").to_string()
+                }
+                ("fills", "fgColor") => fill.1 = color_value(&tag),
+                ("fills", "bgColor") => fill.2 = color_value(&tag),
+                ("borders", "border") => {
+                    for &(attr, key) in [
+                        ("diagonalUp", "diagonal_up"),
+                        ("diagonalDown", "diagonal_down"),
+                    ]
+                    .iter()
+                    {
+                        if tag.attr_bool(attr) == Some(true) {
+                            entry.push((key, Value::Bool(true)));
+                        }
+                    }
+                    if empty {
+                        borders.push(entry.split_off(0));
+                    }
+                }
+                ("borders", "color") => {
+                    if let (Some(side), Some(color)) = (side.as_mut(), color_value(&tag)) {
+                        side.1.push(("color", color));
+                    }
+                }
+                ("borders", name) => {
+                    let name = match name {
+                        "left" | "start" => "left",
+                        "right" | "end" => "right",
+                        "top" => "top",
+                        "bottom" => "bottom",
+                        "diagonal" => "diagonal",
+                        _ => continue,
+                    };
+                    if let Some(style) = tag.attr("style").filter(|&x| x != "none") {
+                        let options = vec![("style", name_value(style))];
+                        if empty {
+                            entry.push((name, keywords(options)));
+                        } else {
+                            side = Some((name, options));
+                        }
+                    }
+                }
+                ("cellXfs", "xf") => {
+                    let get = |attr: &str| tag.attr_parse::<usize>(attr).unwrap_or(0);
+                    match get("numFmtId") as i64 {
+                        0 => (),
+                        id => match num_fmts.get(&id) {
+                            Some(code) => entry.push(("num_format", Value::String(code.clone()))),
+                            _ if id < 164 => entry.push(("num_format", Value::Int(id))),
+                            _ => (),
+                        },
+                    }
+                    // The Normal font is the workbook's default_font, and the rest only
+                    // differ from it in the options that are given.
+                    if get("fontId") > 0 {
+                        if let Some(font) = fonts.get(get("fontId")) {
+                            let default_font = fonts.first().cloned().unwrap_or_default();
+                            entry
+                                .extend(font.iter().filter(|x| !default_font.contains(x)).cloned());
+                        }
+                    }
+                    entry.extend(fills.get(get("fillId")).cloned().unwrap_or_default());
+                    entry.extend(borders.get(get("borderId")).cloned().unwrap_or_default());
+                    if empty {
+                        xfs.push(entry.split_off(0));
+                    }
+                }
+                ("cellXfs", "alignment") => entry.extend(Styles::alignment(&tag)),
+                _ => (),
+            }
+        }
+        Ok(Styles {
+            xfs,
+            default_font: fonts.first().cloned().unwrap_or_default(),
+        })
+    }
+
+    /// The option for one of the elements of a `<font>`.
+    fn font_option(tag: &Tag) -> Option<(&'static str, Value)> {
+        let val = tag.attr("val");
+        let on = Value::Bool(matches!(val, None | Some("1") | Some("true")));
+        Some(match tag.name {
+            "b" => ("bold", on),
+            "i" => ("italic", on),
+            "strike" => ("strike", on),
+            "outline" => ("outline", on),
+            "shadow" => ("shadow", on),
+            "u" => match val.unwrap_or("single") {
+                "single" => ("underline", Value::Bool(true)),
+                "none" => return None,
+                underline => ("underline", name_value(underline)),
+            },
+            "vertAlign" => ("vert_align", name_value(val?)),
+            "sz" => ("size", Value::Float(val?.parse().ok()?)),
+            "color" => ("color", color_value(tag)?),
+            "name" => ("font", Value::String(val?.to_string())),
+            "family" => ("family", Value::Int(val?.parse().ok()?)),
+            "scheme" => ("scheme", name_value(val?)),
+            "charset" => ("charset", Value::Int(val?.parse().ok()?)),
+            _ => return None,
+        })
+    }
+
+    /// The options of a pattern fill, `bg_color` for the usual solid one.
+    fn fill(fill: &mut (String, Option<Value>, Option<Value>)) -> Options {
+        let (pattern, fg_color, bg_color) = ::std::mem::take(fill);
+        match (pattern.as_str(), fg_color) {
+            ("", _) | ("none", _) | ("gray125", None) => vec![],
+            ("solid", Some(color)) => vec![("bg_color", color)],
+            (pattern, fg_color) => {
+                let mut options = vec![("pattern", name_value(pattern))];
+                options.extend(fg_color.map(|x| ("fg_color", x)));
+                options.extend(bg_color.map(|x| ("bg_color", x)));
+                vec![("fill", keywords(options))]
+            }
+        }
+    }
+
+    fn alignment(tag: &Tag) -> Options {
+        let mut re = vec![];
+        if let Some(horizontal) = tag.attr("horizontal") {
+            re.push(("align_horizontal", name_value(horizontal)));
+        }
+        if let Some(vertical) = tag.attr("vertical") {
+            re.push(("align_vertical", name_value(vertical)));
+        }
+        if tag.attr_bool("wrapText") == Some(true) {
+            re.push(("wrap_text", Value::Bool(true)));
+        }
+        match tag.attr_parse::<i64>("textRotation") {
+            Some(255) => re.push(("text_rotation", atom("vertical"))),
+            Some(x) if x > 0 && x <= 180 => re.push(("text_rotation", Value::Int(x))),
+            _ => (),
+        }
+        if let Some(indent) = tag.attr_parse::<i64>("indent").filter(|&x| x > 0) {
+            re.push(("indent", Value::Int(indent)));
+        }
+        if tag.attr_bool("shrinkToFit") == Some(true) {
+            re.push(("shrink_to_fit", Value::Bool(true)));
+        }
+        if tag.attr_bool("justifyLastLine") == Some(true) {
+            re.push(("justify_last_line", Value::Bool(true)));
+        }
+        match tag.attr("readingOrder") {
+            Some("1") => re.push(("reading_order", atom("left_to_right"))),
+            Some("2") => re.push(("reading_order", atom("right_to_left"))),
+            _ => (),
+        }
+        re
+    }
+}
+
+/// A `<color>` (or `<fgColor>`, ...) in the notations the color options take: `#RRGGBB`
+/// (`#AARRGGBB` when not opaque), `{:theme, index, tint}` or `{:indexed, index}`.
+fn color_value(tag: &Tag) -> Option<Value> {
+    if let Some(rgb) = tag.attr("rgb") {
+        let rgb = rgb.to_uppercase();
+        return Some(Value::String(match rgb.len() {
+            8 if rgb.starts_with("FF") => format!("#{}", &rgb[2..]),
+            _ => format!("#{}", rgb),
+        }));
+    }
+    if let Some(theme) = tag.attr_parse::<i64>("theme") {
+        let mut re = vec![atom("theme"), Value::Int(theme)];
+        re.extend(
+            tag.attr_parse::<f64>("tint")
+                .filter(|&x| x != 0.0)
+                .map(Value::Float),
+        );
+        return Some(Value::Tuple(re));
+    }
+    tag.attr_parse::<i64>("indexed")
+        .map(|x| Value::Tuple(vec![atom("indexed"), Value::Int(x)]))
+}
+
+/// Reads a worksheet into the fields of a `%Elixlsx.Sheet{}`.
+struct SheetReader<'b> {
+    strings: &'b Vec<String>,
+    styles: &'b Styles,
+    /// The anchors of shared formulas by their `si`: the formula, its row and column.
+    shared: HashMap<String, (String, i32, i32)>,
+}
+
+impl<'b> SheetReader<'b> {
+    /// `sheet` is the sheet's entry in `workbook.xml`, with its name and state.
+    fn read(&mut self, xml: &str, sheet: &Tag) -> NifResult<Value> {
+        let mut rows: Vec<Vec<Value>> = vec![];
+        let mut col_widths = vec![];
+        let mut col_styles = vec![];
+        let mut hidden_cols = vec![];
+        let mut row_heights = vec![];
+        let mut row_styles = vec![];
+        let mut hidden_rows = vec![];
+        let mut merge_cells = vec![];
+        let mut re = vec![(
+            "name",
+            Value::String(sheet.attr("name").unwrap_or("").to_string()),
+        )];
+        match sheet.attr("state") {
+            Some("hidden") => re.push(("state", atom("hidden"))),
+            Some("veryHidden") => re.push(("state", atom("very_hidden"))),
+            _ => (),
+        }
+
+        let mut reader = XmlReader::new(xml);
+        while let Some(event) = reader.next() {
+            let (tag, empty) = match event? {
+                Event::Start(tag, empty) => (tag, empty),
+                _ => continue,
+            };
+            match tag.name {
+                "sheetView" if tag.attr_bool("showGridLines") == Some(false) => {
+                    re.push(("show_grid_lines", Value::Bool(false)));
+                }
+                "pane" if tag.attr("state").is_some_and(|x| x.starts_with("frozen")) => {
+                    let get = |attr: &str| tag.attr_parse::<f64>(attr).unwrap_or(0.0) as i64;
+                    let pane = vec![Value::Int(get("ySplit")), Value::Int(get("xSplit"))];
+                    re.push(("pane_freeze", Value::Tuple(pane)));
+                }
+                "sheetFormatPr" => {
+                    if let Some(width) = tag.attr_parse::<f64>("defaultColWidth") {
+                        re.push(("default_col_width", Value::Float(width)));
+                    }
+                    if tag.attr_bool("customHeight") == Some(true) {
+                        if let Some(height) = tag.attr_parse::<f64>("defaultRowHeight") {
+                            re.push(("default_row_height", Value::Float(height)));
+                        }
+                    }
+                }
+                "col" => {
+                    let min = tag.attr_parse::<i64>("min").unwrap_or(1);
+                    let max = tag.attr_parse::<i64>("max").unwrap_or(min);
+                    if min < 1 || max < min || max > MAX_COLS {
+                        return Err(Error::BadArg);
+                    }
+                    let style = self.style(tag.attr_parse("style"));
+                    // Columns there for their style or to be hidden carry the default width
+                    // too, which is only theirs when marked custom.
+                    let width = tag.attr_parse::<f64>("width").filter(|_| {
+                        tag.attr_bool("customWidth") == Some(true)
+                            || style.is_empty() && tag.attr("hidden").is_none()
+                    });
+                    for col in min..=max {
+                        if let Some(width) = width {
+                            col_widths.push((Value::Int(col), Value::Float(width)));
+                        }
+                        if tag.attr_bool("hidden") == Some(true) {
+                            hidden_cols.push(Value::Int(col));
+                        }
+                        if !style.is_empty() {
+                            col_styles.push((Value::Int(col), keywords(style.clone())));
+                        }
+                    }
+                }
+                "row" => {
+                    let row = tag.attr_parse::<usize>("r").unwrap_or(rows.len() + 1);
+                    if !(1..=MAX_ROWS).contains(&row) || rows.len() >= MAX_ROWS {
+                        return Err(Error::BadArg);
+                    }
+                    let row = row.max(rows.len() + 1);
+                    rows.resize(row, vec![]);
+                    let key = Value::Int(row as i64);
+                    if tag.attr_bool("customHeight") == Some(true) {
+                        if let Some(height) = tag.attr_parse::<f64>("ht") {
+                            row_heights.push((key.clone(), Value::Float(height)));
+                        }
+                    }
+                    if tag.attr_bool("hidden") == Some(true) {
+                        hidden_rows.push(key.clone());
+                    }
+                    let style = self.style(tag.attr_parse("s"));
+                    if tag.attr_bool("customFormat") == Some(true) && !style.is_empty() {
+                        row_styles.push((key, keywords(style)));
+                    }
+                }
+                "c" => {
+                    if rows.is_empty() {
+                        rows.push(vec![]);
+                    }
+                    let row = rows.len() as i32;
+                    let cells = rows.last_mut().unwrap();
+                    let (row, col) = match tag.attr("r").and_then(from_excel_coords) {
+                        Some((r, col)) if r == row => (r, col),
+                        _ => (row, cells.len() as i32 + 1),
+                    };
+                    if !(1..=MAX_COLS).contains(&(col as i64)) {
+                        return Err(Error::BadArg);
+                    }
+                    let cell = self.read_cell(&mut reader, &tag, empty, row, col)?;
+                    if cell != Value::Nil && col as usize > cells.len() {
+                        cells.resize(col as usize - 1, Value::Nil);
+                        cells.push(cell);
+                    }
+                }
+                "mergeCell" => {
+                    if let Some(range) = tag.attr("ref") {
+                        let mut cells = range.split(':').map(|x| Value::String(x.to_string()));
+                        if let (Some(first), Some(last)) = (cells.next(), cells.next()) {
+                            merge_cells.push(Value::Tuple(vec![first, last]));
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        re.push((
+            "rows",
+            Value::List(rows.into_iter().map(Value::List).collect()),
+        ));
+        re.push(("col_widths", Value::Map(col_widths)));
+        re.push(("row_heights", Value::Map(row_heights)));
+        re.push(("merge_cells", Value::List(merge_cells)));
+        if !col_styles.is_empty() {
+            re.push(("col_styles", Value::Map(col_styles)));
+        }
+        if !row_styles.is_empty() {
+            re.push(("row_styles", Value::Map(row_styles)));
+        }
+        if !hidden_cols.is_empty() {
+            re.push(("hidden_cols", Value::List(hidden_cols)));
+        }
+        if !hidden_rows.is_empty() {
+            re.push(("hidden_rows", Value::List(hidden_rows)));
+        }
+        Ok(fields(re))
+    }
+
+    fn style(&self, index: Option<usize>) -> Options {
+        index
+            .and_then(|x| self.styles.xfs.get(x))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// The cell just started: its value, a formula tuple, or `[value | options]` when it has
+    /// a style. Styled cells without a value are `:empty`; cells with neither, `nil`.
+    fn read_cell(
+        &mut self,
+        reader: &mut XmlReader,
+        tag: &Tag,
+        empty: bool,
+        row: i32,
+        col: i32,
+    ) -> NifResult<Value> {
+        let mut value = None;
+        let mut inline = None;
+        let mut formula = None;
+        if !empty {
+            loop {
+                match reader.next().ok_or(Error::BadArg)?? {
+                    Event::Start(ref tag, false) if tag.name == "v" => {
+                        value = Some(reader.read_text("v")?)
+                    }
+                    Event::Start(ref tag, false) if tag.name == "is" => {
+                        inline = Some(read_rich_text(reader, "is")?)
+                    }
+                    Event::Start(f, f_empty) if f.name == "f" => {
+                        let text = if f_empty {
+                            "".to_string()
+                        } else {
+                            reader.read_text("f")?
+                        };
+                        formula = match f.attr("t") {
+                            Some("array") => Some((text, f.attr("ref").map(|x| x.to_string()))),
+                            Some("shared") => {
+                                let si = f.attr("si").unwrap_or("").to_string();
+                                if text.is_empty() {
+                                    // A member of the shared formula, its anchor's formula moved.
+                                    self.shared.get(&si).and_then(|(anchor, r, c)| {
+                                        shift_formula(anchor, row - r, col - c).map(|x| (x, None))
+                                    })
+                                } else {
+                                    self.shared.insert(si, (text.clone(), row, col));
+                                    Some((text, None))
+                                }
+                            }
+                            // Data tables are the results of what-if analysis rather than formulas.
+                            Some("dataTable") => None,
+                            _ => Some((text, None)),
+                        };
+                    }
+                    Event::End("c") => break,
+                    _ => (),
+                }
+            }
+        }
+
+        let value = match tag.attr("t").unwrap_or("n") {
+            "s" => match value {
+                Some(index) => {
+                    let index = index.trim().parse::<usize>().map_err(|_| Error::BadArg)?;
+                    Some(Value::String(
+                        self.strings.get(index).ok_or(Error::BadArg)?.clone(),
+                    ))
+                }
+                _ => None,
+            },
+            "inlineStr" => inline.map(Value::String),
+            "str" => value.map(|x| Value::String(unescape_text(x))),
+            "b" => value.map(|x| Value::Bool(x.trim() == "1" || x.trim() == "true")),
+            "e" => value.map(|x| Value::Tuple(vec![atom("error"), Value::String(x)])),
+            "d" => value.map(Value::String),
+            _ => value.map(|x| number_value(x.trim())),
+        };
+        let cached = |value: Option<Value>| value.map(|x| keywords(vec![("value", x)]));
+        let cell = match formula {
+            Some((text, None)) => {
+                let mut re = vec![atom("formula"), Value::String(text)];
+                re.extend(cached(value));
+                Value::Tuple(re)
+            }
+            Some((text, Some(range))) => {
+                let mut re = vec![
+                    atom("array_formula"),
+                    Value::String(text),
+                    Value::String(range),
+                ];
+                re.extend(cached(value));
+                Value::Tuple(re)
+            }
+            _ => value.unwrap_or(Value::Nil),
+        };
+        let style = self.style(tag.attr_parse("s"));
+        if style.is_empty() {
+            return Ok(cell);
+        }
+        let mut re = vec![if cell == Value::Nil {
+            atom("empty")
+        } else {
+            cell
+        }];
+        if let Value::List(options) = keywords(style) {
+            re.extend(options);
+        }
+        Ok(Value::List(re))
+    }
+}
+
+/// An integer when the text is one, a float otherwise.
+fn number_value(text: &str) -> Value {
+    match text.parse::<i64>() {
+        Ok(x) => Value::Int(x),
+        _ => match text.parse::<f64>() {
+            Ok(x) if x.is_finite() => Value::Float(x),
+            _ => Value::String(text.to_string()),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relationship_targets() {
+        assert_eq!(
+            resolve_path("xl", "worksheets/sheet1.xml"),
+            "xl/worksheets/sheet1.xml"
+        );
+        assert_eq!(
+            resolve_path("xl/worksheets", "../media/x.png"),
+            "xl/media/x.png"
+        );
+        assert_eq!(resolve_path("xl", "./styles.xml"), "xl/styles.xml");
+        assert_eq!(
+            resolve_path("xl", "/xl/sharedStrings.xml"),
+            "xl/sharedStrings.xml"
+        );
+        assert_eq!(resolve_path("", "xl/workbook.xml"), "xl/workbook.xml");
+    }
+
+    #[test]
+    fn cell_formats() {
+        let xml = r#"<styleSheet>
+            <numFmts count="1"><numFmt numFmtId="164" formatCode="0.000"/></numFmts>
+            <fonts count="2">
+                <font><sz val="11"/><name val="Calibri"/></font>
+                <font><b/><sz val="11"/><name val="Calibri"/></font>
+            </fonts>
+            <fills count="3">
+                <fill><patternFill patternType="none"/></fill>
+                <fill><patternFill patternType="gray125"/></fill>
+                <fill><patternFill patternType="solid"><fgColor rgb="FFFFFF00"/></patternFill></fill>
+            </fills>
+            <borders count="2">
+                <border><left/><right/><top/><bottom/><diagonal/></border>
+                <border>
+                    <left style="thin"><color indexed="10"/></left>
+                    <bottom style="mediumDashed"/>
+                </border>
+            </borders>
+            <cellXfs count="3">
+                <xf numFmtId="0" fontId="0" fillId="0" borderId="0"/>
+                <xf numFmtId="164" fontId="1" fillId="2" borderId="1">
+                    <alignment horizontal="center" wrapText="1"/>
+                </xf>
+                <xf numFmtId="14" fontId="0" fillId="0" borderId="0"/>
+            </cellXfs>
+        </styleSheet>"#;
+        let styles = match Styles::new(xml) {
+            Ok(styles) => styles,
+            Err(_) => panic!("styles.xml not read"),
+        };
+        assert_eq!(styles.xfs.len(), 3);
+        assert!(styles.xfs[0].is_empty());
+        assert_eq!(
+            styles.xfs[1],
+            vec![
+                ("num_format", Value::String("0.000".to_string())),
+                ("bold", Value::Bool(true)),
+                ("bg_color", Value::String("#FFFF00".to_string())),
+                (
+                    "left",
+                    keywords(vec![
+                        ("style", name_value("thin")),
+                        ("color", Value::Tuple(vec![atom("indexed"), Value::Int(10)])),
+                    ])
+                ),
+                (
+                    "bottom",
+                    keywords(vec![("style", name_value("mediumDashed"))])
+                ),
+                ("align_horizontal", name_value("center")),
+                ("wrap_text", Value::Bool(true)),
+            ]
+        );
+        assert_eq!(styles.xfs[2], vec![("num_format", Value::Int(14))]);
+        assert_eq!(
+            styles.default_font,
+            vec![
+                ("size", Value::Float(11.0)),
+                ("font", Value::String("Calibri".to_string())),
+            ]
+        );
+    }
+}
@@ -10,12 +10,14 @@ pub fn create_excel<'a>(
 ) -> ExcelResult<HashMap<String, Vec<u8>>> {
     let mut writer = ExcelWriter::new();
     writer.write_doc_props_dir(&workbook)?;
-    writer.write_rels_dir()?;
+    writer.write_rels_dir(&workbook)?;
     writer.write_xl_dir(&workbook, &mut wci)?;
 
     crate::xml_templates::write_content_types(
         &mut writer.start_file(&"[Content_Types].xml"),
         &wci.sheet_info,
+        !workbook.doc_props.custom.is_empty(),
+        wci.has_dynamic_arrays,
     )?;
     Ok(writer.data)
 }
@@ -35,40 +37,63 @@ impl ExcelWriter {
         self.data.get_mut(&filename.to_string()).unwrap()
     }
 
-    fn write_doc_props_dir(&mut self, workbook: &Workbook) -> ::std::io::Result<&mut Self> {
+    fn write_doc_props_dir(&mut self, workbook: &Workbook) -> ExcelResult<&mut Self> {
         // app.xml
-        self.start_file(&"docProps/app.xml")
-            .write(crate::xml_templates::doc_props_app("1.00".to_string()).as_bytes())?;
+        crate::xml_templates::write_doc_props_app(
+            &mut self.start_file(&"docProps/app.xml"),
+            &workbook.doc_props,
+            "1.00".to_string(),
+        )?;
         // core.xml
-        self.start_file(&"docProps/core.xml").write(
-            crate::xml_templates::doc_props_core(workbook.datetime.clone(), None, None).as_bytes(),
+        crate::xml_templates::write_doc_props_core(
+            &mut self.start_file(&"docProps/core.xml"),
+            &workbook.doc_props,
+            &workbook.datetime,
         )?;
+        // custom.xml
+        if !workbook.doc_props.custom.is_empty() {
+            crate::xml_templates::write_doc_props_custom(
+                &mut self.start_file(&"docProps/custom.xml"),
+                &workbook.doc_props,
+            )?;
+        }
         Ok(self)
     }
 
-    fn write_rels_dir(&mut self) -> ::std::io::Result<()> {
-        self.start_file(&"_rels/.rels")
-            .write(crate::xml_templates::rels_dotrels().as_bytes())?;
+    fn write_rels_dir(&mut self, workbook: &Workbook) -> ::std::io::Result<()> {
+        self.start_file(&"_rels/.rels").write_all(
+            crate::xml_templates::rels_dotrels(!workbook.doc_props.custom.is_empty()).as_bytes(),
+        )?;
         Ok(())
     }
 
     fn write_xl_dir(&mut self, workbook: &Workbook, wci: &mut WorkbookCompInfo) -> ExcelResult<()> {
         self.write_xl_worksheets_dir(workbook, wci)?;
-        crate::xml_templates::write_xl_styles(&mut self.start_file(&"xl/styles.xml"), wci)?;
+        crate::xml_templates::write_xl_styles(
+            &mut self.start_file(&"xl/styles.xml"),
+            workbook,
+            wci,
+        )?;
         crate::xml_templates::wite_string_db(
             &mut self.start_file(&"xl/sharedStrings.xml"),
             &wci.stringdb,
+            workbook.control_chars,
         )?;
         crate::xml_templates::write_workbook_xml(
             &mut self.start_file(&"xl/workbook.xml"),
-            &workbook.sheets,
+            workbook,
             &wci.sheet_info,
         )?;
         crate::xml_templates::write_xl_rels(
             &mut self.start_file(&"xl/_rels/workbook.xml.rels"),
             &wci.sheet_info,
             wci.next_free_xl_rid,
+            wci.has_dynamic_arrays,
         )?;
+        if wci.has_dynamic_arrays {
+            self.start_file(&"xl/metadata.xml")
+                .write_all(crate::xml_templates::dynamic_array_metadata().as_bytes())?;
+        }
         Ok(())
     }
 
@@ -85,7 +110,7 @@ impl ExcelWriter {
             .zip(workbook.sheets.iter())
         {
             let mut writer = self.start_file(&format!("xl/worksheets/{}", filename));
-            crate::xml_templates::write_sheet(&mut writer, sheet, wci)?;
+            crate::xml_templates::write_sheet(&mut writer, sheet, workbook, wci)?;
         }
         Ok(())
     }
@@ -3,12 +3,31 @@ use rustler::types::{ListIterator, MapIterator};
 use rustler::{Decoder, Error};
 use rustler::{NifResult, Term};
 use std::cmp::Eq;
-use std::collections::HashMap;
-use std::hash::Hash;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use crate::date::{decode_date, decode_date_struct, DateOptions};
+use crate::formula::{expand_template, Formula};
+use crate::util::{decode_big_integer, from_excel_coords, Float};
+use crate::wb_compiler::{CellStyle, Color, Font};
+use regex::Regex;
+use crate::xml_writer::ControlChars;
 
 pub struct Workbook<'a> {
     pub sheets: Vec<Sheet<'a>>,
     pub datetime: String,
+    pub control_chars: ControlChars,
+    pub default_font: Font,
+    pub cell_styles: Vec<(String, CellStyle)>,
+    /// Index of the sheet the workbook opens on.
+    pub active_sheet: usize,
+    /// Index of the first tab shown in the tab bar.
+    pub first_visible_tab: usize,
+    /// `xWindow`, `yWindow`, `windowWidth` and `windowHeight`, in twips.
+    pub window: Vec<(&'static str, i32)>,
+    pub doc_props: DocProps,
+    pub values: ValueOptions,
+    pub strings: StringMode,
 }
 
 impl<'a> Decoder<'a> for Workbook<'a> {
@@ -16,6 +35,15 @@ impl<'a> Decoder<'a> for Workbook<'a> {
         let mut wb = Workbook {
             sheets: vec![],
             datetime: ::chrono::Utc::now().format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+            control_chars: Default::default(),
+            default_font: Font::new_default(None),
+            cell_styles: vec![],
+            active_sheet: 0,
+            first_visible_tab: 0,
+            window: vec![],
+            doc_props: Default::default(),
+            values: Default::default(),
+            strings: StringMode::Shared,
         };
         let map = (to_map(term))?;
         if let Some(sheets) = map.get("sheets") {
@@ -34,19 +62,228 @@ impl<'a> Decoder<'a> for Workbook<'a> {
                 _ => (),
             }
         }
+        if let Some(control_chars) = map.get("control_chars") {
+            wb.control_chars = match control_chars.atom_to_string()?.as_str() {
+                "escape" => ControlChars::Escape,
+                "strip" => ControlChars::Strip,
+                _ => return Err(Error::BadArg),
+            }
+        }
+
+        if let Some(font) = map.get("default_font") {
+            let font = Font::new(&decode_keyword_list(font.decode()?)?)?;
+            wb.default_font = Font::new_default(font);
+        }
+
+        if let Some(cell_styles) = map.get("cell_styles") {
+            let cell_styles: ListIterator = cell_styles.decode()?;
+            wb.cell_styles = cell_styles
+                .map(|x| {
+                    let (name, style) = x.decode::<(String, ListIterator)>()?;
+                    Ok((name, CellStyle::new(style)?))
+                })
+                .collect::<NifResult<_>>()?;
+        }
+
+        wb.doc_props = DocProps::new(&map)?;
+
+        if let Some(utc_offset) = map.get("utc_offset") {
+            wb.values.dates.utc_offset = utc_offset.decode()?;
+            if wb.values.dates.utc_offset.abs() >= 86400 {
+                return Err(Error::BadArg);
+            }
+        }
+        if let Some(date1904) = map.get("date1904") {
+            wb.values.dates.date1904 = date1904.decode()?;
+        }
+        if let Some(&strings) = map.get("strings") {
+            wb.strings = StringMode::new(strings)?;
+        }
+        if let Some(long_numbers) = map.get("long_numbers") {
+            wb.values.long_numbers_as_text = match decode_string(*long_numbers)?.as_str() {
+                "number" => false,
+                "text" => true,
+                _ => return Err(Error::BadArg),
+            };
+        }
+
+        let visible = |i: &usize| wb.sheets[*i].state == "visible";
+        let first_visible = (0..wb.sheets.len()).find(&visible);
+        if first_visible.is_none() && wb.sheets.len() > 0 {
+            return Err(Error::BadArg);
+        }
+        wb.active_sheet = match map.get("active_sheet") {
+            Some(&sheet) => Some(wb.get_sheet_index(sheet)?).filter(&visible).ok_or(Error::BadArg)?,
+            _ => first_visible.unwrap_or(0),
+        };
+        wb.first_visible_tab = match map.get("first_visible_tab") {
+            Some(&sheet) => wb.get_sheet_index(sheet)?,
+            _ => first_visible.unwrap_or(0),
+        };
+        if let Some(sheet) = wb.sheets.get_mut(wb.active_sheet) {
+            sheet.view.tab_selected = true;
+        }
+
+        if let Some(window) = map.get("window") {
+            let window = decode_keyword_list(window.decode()?)?;
+            for &(key, attr) in [
+                ("x", "xWindow"),
+                ("y", "yWindow"),
+                ("width", "windowWidth"),
+                ("height", "windowHeight"),
+            ].iter()
+            {
+                if let Some(value) = window.get(key) {
+                    wb.window.push((attr, value.decode()?));
+                }
+            }
+        }
 
         Ok(wb)
     }
 }
 
+impl<'a> Workbook<'a> {
+    /// Finds a sheet by 0-based index or by name.
+    fn get_sheet_index(&self, term: Term<'a>) -> NifResult<usize> {
+        let index = match term.decode::<usize>() {
+            Ok(index) => Some(index),
+            _ => {
+                let name = decode_string(term)?;
+                self.sheets.iter().position(|x| x.name == name)
+            }
+        };
+        index.filter(|&x| x < self.sheets.len()).ok_or(Error::BadArg)
+    }
+}
+
+/// Document properties: `docProps/core.xml`, `docProps/app.xml` and `docProps/custom.xml`.
+#[derive(Default)]
+pub struct DocProps {
+    pub title: Option<String>,
+    pub subject: Option<String>,
+    pub creator: Option<String>,
+    pub last_modified_by: Option<String>,
+    pub keywords: Option<String>,
+    pub category: Option<String>,
+    pub description: Option<String>,
+    pub language: Option<String>,
+    pub company: Option<String>,
+    pub manager: Option<String>,
+    pub application: Option<String>,
+    pub custom: Vec<(String, CustomProperty)>,
+}
+
+/// A custom document property and its variant type.
+pub enum CustomProperty {
+    String(String),
+    Int(i32),
+    Float(f64),
+    Bool(bool),
+    /// `YYYY-MM-DDThh:mm:ssZ`
+    DateTime(String),
+}
+
+impl<'a> DocProps {
+    fn new(map: &HashMap<String, Term<'a>>) -> NifResult<Self> {
+        let get = |key: &str| match map.get(key) {
+            Some(&term) => decode_string(term).map(Some),
+            _ => Ok(None),
+        };
+        // Keywords may be given as a list; Office separates them with semicolons.
+        let keywords = match map.get("keywords") {
+            Some(&term) if term.is_list() || term.is_empty_list() => {
+                let li: ListIterator = term.decode()?;
+                let li = li.map(decode_string).collect::<NifResult<Vec<String>>>()?;
+                Some(li.join("; "))
+            }
+            _ => get("keywords")?,
+        };
+        let custom: Vec<(String, CustomProperty)> = match map.get("custom_properties") {
+            Some(&term) if term.is_map() => term
+                .decode::<MapIterator>()?
+                .map(|(k, v)| Ok((decode_string(k)?, CustomProperty::new(v)?)))
+                .collect::<NifResult<_>>()?,
+            Some(&term) => decode_keyword_list_ordered(term.decode()?)?
+                .into_iter()
+                .map(|(k, v)| Ok((k, CustomProperty::new(v)?)))
+                .collect::<NifResult<_>>()?,
+            _ => vec![],
+        };
+        let mut names = HashSet::new();
+        if !custom.iter().all(|(name, _)| names.insert(name)) {
+            return Err(Error::BadArg);
+        }
+        Ok(DocProps {
+            title: get("title")?,
+            subject: get("subject")?,
+            creator: get("creator")?,
+            last_modified_by: get("last_modified_by")?,
+            keywords: keywords,
+            category: get("category")?,
+            description: get("description")?,
+            language: get("language")?,
+            company: get("company")?,
+            manager: get("manager")?,
+            application: get("application")?,
+            custom: custom,
+        })
+    }
+}
+
+impl<'a> CustomProperty {
+    fn new(term: Term<'a>) -> NifResult<Self> {
+        Ok(match get_type(term) {
+            TermType::Binary => CustomProperty::String(term.decode()?),
+            TermType::Atom => match term.atom_to_string()?.as_str() {
+                "true" => CustomProperty::Bool(true),
+                "false" => CustomProperty::Bool(false),
+                _ => return Err(Error::BadArg),
+            },
+            TermType::Number => match term.decode::<i32>() {
+                Ok(i) => CustomProperty::Int(i),
+                // Integers beyond i4 are kept as doubles, like Excel does.
+                _ => match term.decode::<Float>()?.0 {
+                    x if x.is_finite() => CustomProperty::Float(x),
+                    _ => return Err(Error::BadArg),
+                },
+            },
+            TermType::Tuple => {
+                let ((y, m, d), (h, mm, s)) = term.decode::<((i32, u32, u32), (u32, u32, u32))>()?;
+                let datetime = ::chrono::NaiveDate::from_ymd_opt(y, m, d)
+                    .and_then(|x| x.and_hms_opt(h, mm, s))
+                    .ok_or(Error::BadArg)?;
+                CustomProperty::DateTime(datetime.format("%Y-%m-%dT%H:%M:%SZ").to_string())
+            }
+            _ => return Err(Error::BadArg),
+        })
+    }
+}
+
 pub struct Sheet<'a> {
     pub name: String,
     pub rows: Term<'a>,
-    pub col_widths: HashMap<i32, i32>,
-    pub row_heights: HashMap<i32, i32>,
+    pub col_widths: HashMap<i32, Float>,
+    pub row_heights: HashMap<i32, Float>,
+    pub hidden_cols: HashSet<i32>,
+    pub hidden_rows: HashSet<i32>,
+    pub default_col_width: Option<Float>,
+    pub default_row_height: Option<Float>,
+    /// Styles of whole columns and rows, applied to cells without one of their own.
+    pub col_styles: HashMap<i32, CellStyle>,
+    pub row_styles: HashMap<i32, CellStyle>,
     pub merge_cells: Vec<(String, String)>,
-    pub pane_freeze: Option<(i32, i32)>,
+    pub pane: Option<Pane>,
     pub show_grid_lines: bool,
+    /// `visible`, `hidden` or `veryHidden`, which only VBA can unhide.
+    pub state: String,
+    pub view: SheetView,
+    /// Fit the widths of the columns without a `col_widths` entry to their content.
+    pub auto_width: bool,
+    /// Write runs of the same formula down a column as shared formulas.
+    pub shared_formulas: bool,
+    /// Overrides the workbook's `strings` for this sheet.
+    pub strings: Option<StringMode>,
 }
 
 impl<'a> Decoder<'a> for Sheet<'a> {
@@ -57,18 +294,259 @@ impl<'a> Decoder<'a> for Sheet<'a> {
                 .and_then(|x| x.decode().ok())
                 .unwrap_or("".to_string()),
             rows: map.get("rows").map(|&x| x).ok_or(Error::BadArg)?,
-            col_widths: to_int_map(map.get("col_widths"))?,
-            row_heights: to_int_map(map.get("row_heights"))?,
+            col_widths: to_float_map(map.get("col_widths"))?,
+            row_heights: to_float_map(map.get("row_heights"))?,
+            hidden_cols: decode_int_set(map.get("hidden_cols"))?,
+            hidden_rows: decode_int_set(map.get("hidden_rows"))?,
+            default_col_width: decode_size(map.get("default_col_width"))?,
+            default_row_height: decode_size(map.get("default_row_height"))?,
+            col_styles: to_style_map(map.get("col_styles"))?,
+            row_styles: to_style_map(map.get("row_styles"))?,
             merge_cells: decode_merge_cells(map.get("merge_cells"))?,
-            pane_freeze: map.get("pane_freeze").and_then(|x| x.decode().ok()),
+            pane: Pane::new(&map)?,
             show_grid_lines: map.get("show_grid_lines")
                 .and_then(|&x| x.atom_to_string().ok())
                 .map_or(false, |x| x == "true"),
+            state: match map.get("state") {
+                Some(&state) => match decode_string(state)?.as_str() {
+                    "visible" => "visible".to_string(),
+                    "hidden" => "hidden".to_string(),
+                    "very_hidden" | "veryHidden" => "veryHidden".to_string(),
+                    _ => return Err(Error::BadArg),
+                },
+                _ => "visible".to_string(),
+            },
+            view: SheetView::new(&map)?,
+            auto_width: map.get("auto_width").map_or(false, |x| x.decode().unwrap_or(false)),
+            shared_formulas: map.get("shared_formulas")
+                .map_or(false, |x| x.decode().unwrap_or(false)),
+            strings: match map.get("strings") {
+                Some(&strings) => Some(StringMode::new(strings)?),
+                _ => None,
+            },
         };
         Ok(re)
     }
 }
 
+/// Where the text of string cells goes.
+pub enum StringMode {
+    /// The shared string table, `xl/sharedStrings.xml`, where each text is stored once.
+    Shared,
+    /// The cells themselves, as `t="inlineStr"`; saves building the table when the
+    /// strings are mostly unique.
+    Inline,
+    /// The table for texts that appear more than once in the sheet, by their hashes once
+    /// `for_rows` has counted them, and the cells for the rest.
+    Adaptive(HashSet<u64>),
+}
+
+impl<'a> StringMode {
+    fn new(term: Term<'a>) -> NifResult<Self> {
+        Ok(match decode_string(term)?.as_str() {
+            "shared" => StringMode::Shared,
+            "inline" => StringMode::Inline,
+            "adaptive" => StringMode::Adaptive(HashSet::new()),
+            _ => return Err(Error::BadArg),
+        })
+    }
+
+    /// The mode for a sheet with these rows, with the repeated texts counted when adaptive.
+    pub fn for_rows(&self, rows: Term<'a>) -> NifResult<Self> {
+        Ok(match self {
+            StringMode::Shared => StringMode::Shared,
+            StringMode::Inline => StringMode::Inline,
+            StringMode::Adaptive(_) => {
+                let mut seen = HashSet::new();
+                let mut repeated = HashSet::new();
+                for row in rows.decode::<ListIterator>()? {
+                    for cell in row.decode::<ListIterator>()? {
+                        let value = if cell.is_list() {
+                            cell.decode::<ListIterator>()?.next()
+                        } else {
+                            Some(cell)
+                        };
+                        if let Some(Ok(text)) = value.map(|x| x.decode::<&str>()) {
+                            let hash = hash_str(text);
+                            if !seen.insert(hash) {
+                                repeated.insert(hash);
+                            }
+                        }
+                    }
+                }
+                StringMode::Adaptive(repeated)
+            }
+        })
+    }
+
+    pub fn is_shared(&self, text: &str) -> bool {
+        match self {
+            StringMode::Shared => true,
+            StringMode::Inline => false,
+            StringMode::Adaptive(repeated) => repeated.contains(&hash_str(text)),
+        }
+    }
+}
+
+fn hash_str(text: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    text.hash(&mut hasher);
+    hasher.finish()
+}
+
+pub enum Pane {
+    /// Rows and columns kept in view, from `pane_freeze: {rows, cols}`.
+    Frozen(i32, i32),
+    /// Position of the split in twips (1/20 pt) and the first cell of the bottom right pane,
+    /// from `pane_split: {x, y}` or `{x, y, top_left_cell}`.
+    Split(i32, i32, Option<String>),
+}
+
+impl<'a> Pane {
+    fn new(map: &HashMap<String, Term<'a>>) -> NifResult<Option<Self>> {
+        // `nil`, the default of `Sheet`'s `pane_freeze`, is the same as leaving it out.
+        let get = |key| {
+            map.get(key)
+                .filter(|x| !x.is_atom() || x.atom_to_string().ok().as_deref() != Some("nil"))
+        };
+        match (get("pane_freeze"), get("pane_split")) {
+            (Some(_), Some(_)) => Err(Error::BadArg),
+            (Some(&term), None) => {
+                let (rows, cols) = term.decode::<(i32, i32)>()?;
+                if rows < 0 || cols < 0 {
+                    return Err(Error::BadArg);
+                }
+                Ok(if rows > 0 || cols > 0 {
+                    Some(Pane::Frozen(rows, cols))
+                } else {
+                    None
+                })
+            }
+            (None, Some(&term)) => {
+                let li = ::rustler::types::tuple::get_tuple(term)?;
+                let (x, y) = match li.len() {
+                    2 | 3 => (li[0].decode::<i32>()?, li[1].decode::<i32>()?),
+                    _ => return Err(Error::BadArg),
+                };
+                let top_left_cell = match li.get(2) {
+                    Some(&cell) => Some(decode_string(cell)?),
+                    _ => None,
+                };
+                if x < 0 || y < 0 || !top_left_cell.iter().all(|x| from_excel_coords(x).is_some()) {
+                    return Err(Error::BadArg);
+                }
+                Ok(if x > 0 || y > 0 {
+                    Some(Pane::Split(x, y, top_left_cell))
+                } else {
+                    None
+                })
+            }
+            (None, None) => Ok(None),
+        }
+    }
+}
+
+/// How the sheet is shown when the workbook is opened.
+pub struct SheetView {
+    /// 10 to 400 percent.
+    pub zoom: Option<i32>,
+    pub right_to_left: bool,
+    pub show_formulas: bool,
+    pub show_zeros: bool,
+    pub show_headers: bool,
+    /// `normal`, `pageLayout` or `pageBreakPreview`.
+    pub view: String,
+    pub tab_color: Color,
+    pub active_cell: Option<String>,
+    /// Selected ranges, `sqref`.
+    pub selection: Vec<String>,
+    /// Set on the workbook's active sheet.
+    pub tab_selected: bool,
+}
+
+impl<'a> SheetView {
+    fn new(map: &HashMap<String, Term<'a>>) -> NifResult<Self> {
+        lazy_static! {
+            static ref CELL: Regex = Regex::new(r"^\$?[A-Z]{1,3}\$?[0-9]+$").unwrap();
+            static ref RANGE: Regex =
+                Regex::new(r"^\$?[A-Z]{1,3}\$?[0-9]+(:\$?[A-Z]{1,3}\$?[0-9]+)?$").unwrap();
+        }
+        fn get_bool<'a>(map: &HashMap<String, Term<'a>>, key: &str, default: bool) -> NifResult<bool> {
+            map.get(key).map_or(Ok(default), |x| x.decode())
+        }
+
+        let zoom = match map.get("zoom") {
+            Some(zoom) => match zoom.decode::<i32>()? {
+                x @ 10..=400 => Some(x),
+                _ => return Err(Error::BadArg),
+            },
+            _ => None,
+        };
+        let view = match map.get("view") {
+            Some(&view) => match decode_string(view)?.as_str() {
+                "normal" => "normal".to_string(),
+                "page_layout" => "pageLayout".to_string(),
+                "page_break_preview" => "pageBreakPreview".to_string(),
+                _ => return Err(Error::BadArg),
+            },
+            _ => "normal".to_string(),
+        };
+        let active_cell = match map.get("active_cell") {
+            Some(&cell) => Some(decode_string(cell)?),
+            _ => None,
+        };
+        let selection = match map.get("selection") {
+            Some(&range) if range.is_list() || range.is_empty_list() => {
+                let ranges: ListIterator = range.decode()?;
+                ranges.map(decode_string).collect::<NifResult<_>>()?
+            }
+            Some(&range) => vec![decode_string(range)?],
+            _ => vec![],
+        };
+        if !active_cell.iter().all(|x| CELL.is_match(x))
+            || !selection.iter().all(|x| RANGE.is_match(x))
+        {
+            return Err(Error::BadArg);
+        }
+
+        Ok(SheetView {
+            zoom: zoom,
+            right_to_left: get_bool(map, "right_to_left", false)?,
+            show_formulas: get_bool(map, "show_formulas", false)?,
+            show_zeros: get_bool(map, "show_zeros", true)?,
+            show_headers: get_bool(map, "show_headers", true)?,
+            view: view,
+            tab_color: map.get("tab_color").map_or(Ok(Color::None), |x| x.decode())?,
+            active_cell: active_cell,
+            selection: selection,
+            tab_selected: false,
+        })
+    }
+
+    /// Whether an active cell or selection was given.
+    pub fn has_selection(&self) -> bool {
+        self.active_cell.is_some() || !self.selection.is_empty()
+    }
+
+    /// The active cell, by default the first cell of the selection.
+    pub fn get_active_cell(&self) -> String {
+        match (&self.active_cell, self.selection.first()) {
+            (Some(cell), _) => cell.clone(),
+            (_, Some(range)) => range.split(':').next().unwrap_or("A1").to_string(),
+            _ => "A1".to_string(),
+        }
+    }
+
+    /// The selected ranges, by default just the active cell.
+    pub fn get_sqref(&self) -> String {
+        if self.selection.is_empty() {
+            self.get_active_cell()
+        } else {
+            self.selection.join(" ")
+        }
+    }
+}
+
 fn to_map<'a>(term: Term<'a>) -> NifResult<HashMap<String, Term<'a>>> {
     let re = term.decode::<MapIterator>()?
         .filter_map(|(k, v)| match k.atom_to_string() {
@@ -79,13 +557,41 @@ fn to_map<'a>(term: Term<'a>) -> NifResult<HashMap<String, Term<'a>>> {
     Ok(re)
 }
 
-fn to_int_map<'a>(term: Option<&Term<'a>>) -> NifResult<HashMap<i32, i32>> {
+fn to_float_map<'a>(term: Option<&Term<'a>>) -> NifResult<HashMap<i32, Float>> {
     match term {
         Some(term) => decode_hash_map(*term),
         _ => Ok(Default::default()),
     }
 }
 
+/// A column width or row height, which has to be positive.
+fn decode_size<'a>(term: Option<&Term<'a>>) -> NifResult<Option<Float>> {
+    match term {
+        Some(term) => match term.decode()? {
+            Float(size) if size > 0.0 => Ok(Some(Float(size))),
+            _ => Err(Error::BadArg),
+        },
+        _ => Ok(None),
+    }
+}
+
+fn decode_int_set<'a>(term: Option<&Term<'a>>) -> NifResult<HashSet<i32>> {
+    match term {
+        Some(term) => Ok(term.decode::<Vec<i32>>()?.into_iter().collect()),
+        _ => Ok(Default::default()),
+    }
+}
+
+fn to_style_map<'a>(term: Option<&Term<'a>>) -> NifResult<HashMap<i32, CellStyle>> {
+    match term {
+        Some(term) => decode_hash_map::<i32, ListIterator>(*term)?
+            .into_iter()
+            .map(|(k, v)| Ok((k, CellStyle::new(v)?)))
+            .collect(),
+        _ => Ok(Default::default()),
+    }
+}
+
 fn decode_merge_cells<'a>(term: Option<&Term<'a>>) -> NifResult<Vec<(String, String)>> {
     match term {
         Some(term) => {
@@ -108,53 +614,105 @@ pub fn decode_keyword_list<'a>(list: ListIterator<'a>) -> NifResult<HashMap<Stri
     list.map(|x| {
         let x = ::rustler::types::tuple::get_tuple(x)?;
         if x.len() == 2 {
-            Ok((x[0].decode::<String>()?, x[1]))
+            Ok((decode_string(x[0])?, x[1]))
         } else {
             Err(Error::BadArg)
         }
     }).collect()
 }
 
+/// Whether `range` is an A1 reference to a cell or a range of cells.
+fn from_excel_range(range: &str) -> bool {
+    let mut cells = range.split(':');
+    let valid = cells.next().map_or(false, |x| from_excel_coords(x).is_some());
+    valid && cells.next().map_or(true, |x| from_excel_coords(x).is_some()) && cells.next().is_none()
+}
+
+/// Like `decode_keyword_list`, keeping the order of the list.
+pub fn decode_keyword_list_ordered<'a>(list: ListIterator<'a>) -> NifResult<Vec<(String, Term<'a>)>> {
+    list.map(|x| {
+        let (k, v) = x.decode::<(Term, Term)>()?;
+        Ok((decode_string(k)?, v))
+    }).collect()
+}
+
+/// Decodes an atom or a binary into a `String`, as keyword keys and option values may be either.
+pub fn decode_string<'a>(term: Term<'a>) -> NifResult<String> {
+    match get_type(term) {
+        TermType::Atom => term.atom_to_string(),
+        _ => term.decode(),
+    }
+}
+
 pub enum CellValue {
-    Formula(String, HashMap<String, String>),
+    Formula(Formula),
     String(String),
     Number(String),
+    /// A number Excel would round, written as text with `long_numbers: :text`.
+    NumberText(String),
+    /// A date or time as a serial number, and the format code it is shown with when the
+    /// cell has none.
+    Date(String, &'static str),
+    /// One of Excel's error values, `#N/A`, `#DIV/0!`, ...
+    Error(String),
     Empty,
     None,
 }
 
 impl<'a> CellValue {
-    pub fn new(term: Term<'a>, is_date: bool) -> NifResult<Self> {
-        lazy_static! {
-            static ref EXCEL_EPOCH: i64 = ::chrono::NaiveDate::from_ymd(1899, 12, 31)
-                .and_hms(0, 0, 0)
-                .timestamp();
-        }
-        Ok(match (get_type(term), is_date) {
-            (TermType::Tuple, true) => {
-                let ((y, m, d), (h, mm, s)) = term.decode::<((i32, u32, u32), (u32, u32, u32))>()?;
-                let mut v = (::chrono::NaiveDate::from_ymd(y, m, d)
-                    .and_hms(h, mm, s)
-                    .timestamp() - *EXCEL_EPOCH) as f64 / 86400.0;
-                // Apply the "Lotus 123" bug - 1900 is considered a leap year.
-                if v > 59.0 {
-                    v = v + 1.0;
-                }
-                CellValue::Number(v.to_string())
+    /// Decodes the value of a cell in `row`, which `formula_template`s are filled in for.
+    pub fn new(term: Term<'a>, row: i32, options: &ValueOptions) -> NifResult<Self> {
+        // Structs: dates, times, durations and decimals.
+        if term.is_map() {
+            let map = decode_options(term)?;
+            if let Some((serial, format)) = decode_date_struct(&map, &options.dates)? {
+                return Ok(CellValue::Date(serial, format));
             }
-            (TermType::Tuple, false) => {
+            if let Some(error) = decimal_error(&map)? {
+                return Ok(CellValue::Error(error.to_string()));
+            }
+            return Ok(match decode_decimal(&map)? {
+                Some(num) => CellValue::number(num, options),
+                _ => CellValue::None,
+            });
+        }
+        if let Some((serial, format)) = decode_date(term, &options.dates)? {
+            return Ok(CellValue::Date(serial, format));
+        }
+        if let Some(error) = non_finite_error(term)? {
+            return Ok(CellValue::Error(error.to_string()));
+        }
+        Ok(match get_type(term) {
+            TermType::Tuple => {
                 let li = ::rustler::types::tuple::get_tuple(term)?;
-                if li.len() >= 2 && li.len() <= 3 {
-                    let t: &str = li[0].decode()?;
-                    match t {
-                        "excelts" => CellValue::Number(li[1].decode()?),
-                        "formula" => {
+                // The cached value of a formula, from its options.
+                let get_value = |opts: Option<&Term<'a>>| match opts {
+                    Some(&opts) => match decode_options(opts)?.get("value") {
+                        Some(&value) => CachedValue::new(value),
+                        _ => Ok(None),
+                    },
+                    _ => Ok(None),
+                };
+                if li.len() >= 2 && li.len() <= 4 {
+                    match li[0].atom_to_string()?.as_str() {
+                        "excelts" => CellValue::Number(decode_number(li[1])?),
+                        "error" if li.len() == 2 => CellValue::Error(CachedValue::decode_error(li[1])?),
+                        "formula" if li.len() <= 3 => {
+                            let formula: String = li[1].decode()?;
+                            CellValue::Formula(Formula::new(&formula, None, get_value(li.get(2))?)?)
+                        }
+                        "formula_template" if li.len() <= 3 => {
+                            let formula = expand_template(&li[1].decode::<String>()?, row)?;
+                            CellValue::Formula(Formula::new(&formula, None, get_value(li.get(2))?)?)
+                        }
+                        "array_formula" if li.len() >= 3 => {
                             let formula: String = li[1].decode()?;
-                            let mut opts: HashMap<String, String> = HashMap::new();
-                            if li.len() == 3 {
-                                opts = decode_hash_map(li[2])?;
+                            let range = decode_string(li[2])?;
+                            if !from_excel_range(&range) {
+                                return Err(Error::BadArg);
                             }
-                            CellValue::Formula(formula, opts)
+                            let value = get_value(li.get(3))?;
+                            CellValue::Formula(Formula::new(&formula, Some(range), value)?)
                         }
                         _ => CellValue::None,
                     }
@@ -162,10 +720,12 @@ impl<'a> CellValue {
                     CellValue::None
                 }
             }
-            (TermType::Number, _) => CellValue::Number(term.decode::<String>()?),
-            (TermType::Binary, _) => CellValue::String(term.decode::<String>()?),
-            (TermType::Atom, _) => {
-                if term.decode::<String>()? == "empty" {
+            // Floats never have more digits than Excel keeps.
+            TermType::Number if term.decode::<f64>().is_ok() => CellValue::Number(decode_number(term)?),
+            TermType::Number => CellValue::number(decode_number(term)?, options),
+            TermType::Binary => CellValue::String(term.decode::<String>()?),
+            TermType::Atom => {
+                if term.atom_to_string()? == "empty" {
                     CellValue::Empty
                 } else {
                     CellValue::None
@@ -175,4 +735,221 @@ impl<'a> CellValue {
             _ => CellValue::None,
         })
     }
+
+    /// A number cell, or a text one for numbers Excel would round with `long_numbers: :text`.
+    fn number(num: String, options: &ValueOptions) -> Self {
+        if options.long_numbers_as_text && significant_digits(&num) > 15 {
+            CellValue::NumberText(num)
+        } else {
+            CellValue::Number(num)
+        }
+    }
+}
+
+/// The error Excel shows in place of a value that isn't a finite number: the `:nan`,
+/// `:infinity` and `:neg_infinity` atoms, or a `%Decimal{}` that is `NaN` or infinite.
+fn non_finite_error<'a>(term: Term<'a>) -> NifResult<Option<&'static str>> {
+    if term.is_atom() {
+        Ok(non_finite_name_error(&term.atom_to_string()?))
+    } else if term.is_map() {
+        decimal_error(&decode_options(term)?)
+    } else {
+        Ok(None)
+    }
+}
+
+/// `non_finite_error` of a decoded map.
+fn decimal_error<'a>(map: &HashMap<String, Term<'a>>) -> NifResult<Option<&'static str>> {
+    match (map.get("__struct__"), map.get("coef")) {
+        (Some(&name), Some(&coef)) if coef.is_atom() && decode_string(name)? == "Elixir.Decimal" => {
+            Ok(non_finite_name_error(&coef.atom_to_string()?))
+        }
+        _ => Ok(None),
+    }
+}
+
+fn non_finite_name_error(name: &str) -> Option<&'static str> {
+    match name {
+        "nan" | "NaN" => Some("#NUM!"),
+        "infinity" | "neg_infinity" | "inf" => Some("#DIV/0!"),
+        _ => None,
+    }
+}
+
+/// Workbook options that change how cell values are decoded.
+#[derive(Default, Clone, Copy)]
+pub struct ValueOptions {
+    pub dates: DateOptions,
+    /// Write integers and decimals with more significant digits than the 15 Excel keeps as
+    /// text, so they aren't rounded.
+    pub long_numbers_as_text: bool,
+}
+
+/// The cached result of a formula, shown by readers that don't recalculate.
+pub enum CachedValue {
+    Number(String),
+    String(String),
+    Bool(bool),
+    /// One of Excel's error values, `#N/A`, `#DIV/0!`, ...
+    Error(String),
+}
+
+impl<'a> CachedValue {
+    pub const ERRORS: [&'static str; 14] = [
+        "#NULL!", "#DIV/0!", "#VALUE!", "#REF!", "#NAME?", "#NUM!", "#N/A", "#GETTING_DATA",
+        "#SPILL!", "#CALC!", "#FIELD!", "#BLOCKED!", "#CONNECT!", "#BUSY!",
+    ];
+
+    /// Decodes a number (`%Decimal{}` included), string, boolean or `{:error, "#N/A"}`;
+    /// `nil` is no value.
+    fn new(term: Term<'a>) -> NifResult<Option<Self>> {
+        if let Some(error) = non_finite_error(term)? {
+            return Ok(Some(CachedValue::Error(error.to_string())));
+        }
+        Ok(Some(match get_type(term) {
+            TermType::Number | TermType::Map => CachedValue::Number(decode_number(term)?),
+            TermType::Binary => CachedValue::String(term.decode()?),
+            TermType::Atom if term.atom_to_string()? == "nil" => return Ok(None),
+            TermType::Atom => CachedValue::Bool(term.decode()?),
+            TermType::Tuple => {
+                let (tag, error) = term.decode::<(Term, Term)>()?;
+                if tag.atom_to_string()? != "error" {
+                    return Err(Error::BadArg);
+                }
+                CachedValue::Error(CachedValue::decode_error(error)?)
+            }
+            _ => return Err(Error::BadArg),
+        }))
+    }
+
+    /// One of `ERRORS`, in any case.
+    fn decode_error(term: Term<'a>) -> NifResult<String> {
+        let error = decode_string(term)?;
+        CachedValue::ERRORS
+            .iter()
+            .find(|x| x.eq_ignore_ascii_case(&error))
+            .map(|x| x.to_string())
+            .ok_or(Error::BadArg)
+    }
+
+    /// The `t` attribute of the cell and the text of its `<v>`.
+    pub fn get_type_and_text(&self) -> (Option<&'static str>, String) {
+        match self {
+            CachedValue::Number(x) => (None, x.clone()),
+            CachedValue::String(x) => (Some("str"), x.clone()),
+            CachedValue::Bool(x) => (Some("b"), (if *x { "1" } else { "0" }).to_string()),
+            CachedValue::Error(x) => (Some("e"), x.clone()),
+        }
+    }
+}
+
+/// Decodes an integer, a float or a `%Decimal{}` into the text of a `<v>`. Integers and
+/// decimals are written out exactly, however many digits they have.
+pub fn decode_number<'a>(term: Term<'a>) -> NifResult<String> {
+    if term.is_map() {
+        return decode_decimal(&decode_options(term)?)?.ok_or(Error::BadArg);
+    }
+    if let Ok(x) = term.decode::<f64>() {
+        // Rust never uses an exponent, which would spell out 1e300 digit by digit.
+        return Ok(if x != 0.0 && (x.abs() >= 1e15 || x.abs() < 1e-5) {
+            format!("{:E}", x)
+        } else {
+            x.to_string()
+        });
+    }
+    match term.decode::<i64>() {
+        Ok(i) => Ok(i.to_string()),
+        // Only integers too big for an `i64` are left, which are read from their encoding.
+        _ => decode_big_integer(term),
+    }
+}
+
+/// The exact value of a decoded `%Decimal{}`; `None` for other maps.
+fn decode_decimal<'a>(map: &HashMap<String, Term<'a>>) -> NifResult<Option<String>> {
+    match map.get("__struct__") {
+        Some(&name) if decode_string(name)? == "Elixir.Decimal" => (),
+        _ => return Ok(None),
+    }
+    let get = |key: &str| map.get(key).copied().ok_or(Error::BadArg);
+    let sign: i64 = get("sign")?.decode()?;
+    let exp: i64 = get("exp")?.decode()?;
+    let coef = match get("coef")?.decode::<i64>() {
+        Ok(coef) => coef.to_string(),
+        _ => decode_big_integer(get("coef")?)?,
+    };
+    Ok(Some(decimal_text(sign, &coef, exp)))
+}
+
+/// Writes `sign * coef * 10^exp` out in full, or with an exponent when that would take
+/// more than 20 zeros.
+fn decimal_text(sign: i64, coef: &str, exp: i64) -> String {
+    let digits = coef.trim_start_matches('0');
+    let len = digits.len() as i64;
+    let mut re = if digits.is_empty() {
+        "0".to_string()
+    } else if exp >= 0 && len + exp <= 20 {
+        digits.to_string() + &"0".repeat(exp as usize)
+    } else if exp < 0 && len + exp > 0 {
+        let point = (len + exp) as usize;
+        format!("{}.{}", &digits[..point], &digits[point..])
+    } else if exp < 0 && len + exp > -20 {
+        format!("0.{}{}", "0".repeat(-(len + exp) as usize), digits)
+    } else {
+        format!("{}E{:+}", digits, exp)
+    };
+    if re.contains('.') {
+        re = re.trim_end_matches('0').trim_end_matches('.').to_string();
+    }
+    if sign < 0 && re != "0" {
+        re.insert(0, '-');
+    }
+    re
+}
+
+/// Digits of a number from the first to the last one that isn't zero.
+fn significant_digits(num: &str) -> usize {
+    let mantissa = num.split(['E', 'e']).next().unwrap_or("");
+    let digits: String = mantissa.chars().filter(|c| c.is_ascii_digit()).collect();
+    digits.trim_start_matches('0').trim_end_matches('0').len()
+}
+
+/// Decodes options given as a keyword list or as a map with atom or string keys.
+pub fn decode_options<'a>(term: Term<'a>) -> NifResult<HashMap<String, Term<'a>>> {
+    if term.is_map() {
+        term.decode::<MapIterator>()?
+            .map(|(k, v)| Ok((decode_string(k)?, v)))
+            .collect()
+    } else {
+        decode_keyword_list(term.decode()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decimals_are_written_exactly() {
+        assert_eq!(decimal_text(1, "1999", -2), "19.99");
+        assert_eq!(decimal_text(-1, "5", -3), "-0.005");
+        assert_eq!(decimal_text(1, "12", 2), "1200");
+        assert_eq!(decimal_text(1, "12500", -3), "12.5");
+        assert_eq!(decimal_text(1, "100", -2), "1");
+        assert_eq!(decimal_text(-1, "0", -2), "0");
+    }
+
+    #[test]
+    fn decimal_exponents_past_20_zeros() {
+        assert_eq!(decimal_text(1, "1", 19), "10000000000000000000");
+        assert_eq!(decimal_text(1, "1", 20), "1E+20");
+        assert_eq!(decimal_text(1, "1", -20), "0.00000000000000000001");
+        assert_eq!(decimal_text(-1, "15", -22), "-15E-22");
+    }
+
+    #[test]
+    fn significant_digits_skip_leading_and_trailing_zeros() {
+        assert_eq!(significant_digits("0.00012300"), 3);
+        assert_eq!(significant_digits("-1234567890123456789"), 19);
+        assert_eq!(significant_digits("1.5E+20"), 2);
+    }
 }
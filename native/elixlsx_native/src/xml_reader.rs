@@ -0,0 +1,354 @@
+use regex::{Captures, Regex};
+use rustler::{Error, NifResult};
+use std::borrow::Cow;
+use std::str::FromStr;
+
+/// A tag or text in an XML document, as read by `XmlReader`.
+pub enum Event<'a> {
+    /// An opening tag, and whether it closes itself (`<col/>`).
+    Start(Tag<'a>, bool),
+    /// A closing tag, by its name.
+    End(&'a str),
+    /// Text between tags, with its entities decoded.
+    Text(Cow<'a, str>),
+}
+
+/// An opening tag. Names of tags and attributes lose their namespace prefix, so `r:id`
+/// is found as `id`.
+pub struct Tag<'a> {
+    pub name: &'a str,
+    attrs: Vec<(&'a str, Cow<'a, str>)>,
+}
+
+impl<'a> Tag<'a> {
+    pub fn attr(&self, name: &str) -> Option<&str> {
+        self.attrs
+            .iter()
+            .find(|x| x.0 == name)
+            .map(|x| x.1.as_ref())
+    }
+
+    /// The attribute parsed as a number; `None` when it is missing or not a number.
+    pub fn attr_parse<T: FromStr>(&self, name: &str) -> Option<T> {
+        self.attr(name).and_then(|x| x.parse().ok())
+    }
+
+    /// A boolean attribute, `1` or `true`.
+    pub fn attr_bool(&self, name: &str) -> Option<bool> {
+        self.attr(name).map(|x| x == "1" || x == "true")
+    }
+}
+
+/// A pull parser over the parts of an `.xlsx` package. Declarations, comments and
+/// DOCTYPEs are skipped; CDATA sections come out as text.
+pub struct XmlReader<'a> {
+    xml: &'a str,
+    pos: usize,
+}
+
+impl<'a> XmlReader<'a> {
+    pub fn new(xml: &'a str) -> Self {
+        XmlReader {
+            xml: xml.trim_start_matches('\u{feff}'),
+            pos: 0,
+        }
+    }
+
+    /// All the text up to the end of the element just started, `name`, nested elements'
+    /// included.
+    pub fn read_text(&mut self, name: &str) -> NifResult<String> {
+        let mut re = String::new();
+        let mut depth = 0;
+        for event in self.by_ref() {
+            match event? {
+                Event::Text(text) => re.push_str(&text),
+                Event::Start(tag, false) if tag.name == name => depth += 1,
+                Event::End(end) if end == name => {
+                    if depth == 0 {
+                        return Ok(re);
+                    }
+                    depth -= 1;
+                }
+                _ => (),
+            }
+        }
+        Err(Error::BadArg)
+    }
+
+    /// Skips to the end of the element just started, `name`.
+    pub fn skip(&mut self, name: &str) -> NifResult<()> {
+        let mut depth = 0;
+        for event in self.by_ref() {
+            match event? {
+                Event::Start(tag, false) if tag.name == name => depth += 1,
+                Event::End(end) if end == name => {
+                    if depth == 0 {
+                        return Ok(());
+                    }
+                    depth -= 1;
+                }
+                _ => (),
+            }
+        }
+        Err(Error::BadArg)
+    }
+
+    /// Moves past `end`, failing when the document stops before it.
+    fn skip_past(&mut self, end: &str) -> NifResult<&'a str> {
+        let xml = self.xml;
+        let rest = &xml[self.pos..];
+        let len = rest.find(end).ok_or(Error::BadArg)?;
+        self.pos = self.pos + len + end.len();
+        Ok(&rest[..len])
+    }
+
+    fn read_tag(&mut self) -> NifResult<Event<'a>> {
+        let xml = self.xml;
+        let rest = &xml[self.pos + 1..];
+        let len = rest
+            .find(|c: char| c.is_whitespace() || c == '/' || c == '>')
+            .ok_or(Error::BadArg)?;
+        let name = local_name(&rest[..len]);
+        let mut rest = &rest[len..];
+        let mut attrs = vec![];
+        loop {
+            rest = rest.trim_start();
+            if rest.starts_with("/>") {
+                rest = &rest[2..];
+                break;
+            }
+            if rest.starts_with('>') {
+                rest = &rest[1..];
+                self.pos = self.xml.len() - rest.len();
+                return Ok(Event::Start(Tag { name, attrs }, false));
+            }
+            let eq = rest.find('=').ok_or(Error::BadArg)?;
+            let attr = local_name(rest[..eq].trim());
+            rest = rest[eq + 1..].trim_start();
+            let quote = match rest.chars().next() {
+                Some(c) if c == '"' || c == '\'' => c,
+                _ => return Err(Error::BadArg),
+            };
+            let end = rest[1..].find(quote).ok_or(Error::BadArg)?;
+            attrs.push((attr, unescape(&rest[1..end + 1])?));
+            rest = &rest[end + 2..];
+        }
+        self.pos = self.xml.len() - rest.len();
+        Ok(Event::Start(Tag { name, attrs }, true))
+    }
+
+    fn read_event(&mut self) -> NifResult<Option<Event<'a>>> {
+        loop {
+            let xml = self.xml;
+            let rest = &xml[self.pos..];
+            if rest.is_empty() {
+                return Ok(None);
+            }
+            if !rest.starts_with('<') {
+                let len = rest.find('<').unwrap_or(rest.len());
+                self.pos += len;
+                return Ok(Some(Event::Text(unescape(&rest[..len])?)));
+            }
+            if rest.starts_with("<![CDATA[") {
+                self.pos += 9;
+                return Ok(Some(Event::Text(Cow::Borrowed(self.skip_past("]]>")?))));
+            }
+            if rest.starts_with("<?") {
+                self.skip_past("?>")?;
+            } else if rest.starts_with("<!--") {
+                self.skip_past("-->")?;
+            } else if rest.starts_with("<!") {
+                self.skip_past(">")?;
+            } else if rest.starts_with("</") {
+                self.pos += 2;
+                let name = self.skip_past(">")?;
+                return Ok(Some(Event::End(local_name(name.trim()))));
+            } else {
+                return self.read_tag().map(Some);
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for XmlReader<'a> {
+    type Item = NifResult<Event<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.read_event() {
+            Ok(event) => event.map(Ok),
+            Err(err) => {
+                // Nothing more can be read from a malformed document.
+                self.pos = self.xml.len();
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+fn local_name(name: &str) -> &str {
+    name.rsplit(':').next().unwrap_or(name)
+}
+
+/// Decodes the predefined entities and character references in `text`.
+fn unescape(text: &str) -> NifResult<Cow<'_, str>> {
+    if !text.contains('&') {
+        return Ok(Cow::Borrowed(text));
+    }
+    let mut re = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(i) = rest.find('&') {
+        re.push_str(&rest[..i]);
+        rest = &rest[i + 1..];
+        let end = rest.find(';').ok_or(Error::BadArg)?;
+        let c = match &rest[..end] {
+            "lt" => '<',
+            "gt" => '>',
+            "amp" => '&',
+            "quot" => '"',
+            "apos" => '\'',
+            entity if entity.starts_with("#x") => u32::from_str_radix(&entity[2..], 16)
+                .ok()
+                .and_then(::std::char::from_u32)
+                .ok_or(Error::BadArg)?,
+            entity if entity.starts_with('#') => entity[1..]
+                .parse::<u32>()
+                .ok()
+                .and_then(::std::char::from_u32)
+                .ok_or(Error::BadArg)?,
+            _ => return Err(Error::BadArg),
+        };
+        re.push(c);
+        rest = &rest[end + 1..];
+    }
+    re.push_str(rest);
+    Ok(Cow::Owned(re))
+}
+
+/// Decodes the `_xHHHH_` escapes that SpreadsheetML cell text carries characters XML
+/// can't in, the reverse of `xml_escape_text`.
+pub fn unescape_text(text: String) -> String {
+    lazy_static! {
+        static ref ESCAPE: Regex = Regex::new("_x([0-9A-Fa-f]{4})_").unwrap();
+    }
+    if !text.contains("_x") {
+        return text;
+    }
+    ESCAPE
+        .replace_all(&text, |caps: &Captures| {
+            u32::from_str_radix(&caps[1], 16)
+                .ok()
+                .and_then(::std::char::from_u32)
+                .map_or_else(|| caps[0].to_string(), |c| c.to_string())
+        })
+        .into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The events of `xml` written out again, with attributes as `name=value`.
+    fn events(xml: &str) -> Option<Vec<String>> {
+        XmlReader::new(xml)
+            .map(|event| {
+                Ok(match event? {
+                    Event::Start(tag, empty) => {
+                        let attrs: String = tag
+                            .attrs
+                            .iter()
+                            .map(|(k, v)| format!(" {}={}", k, v))
+                            .collect();
+                        format!("<{}{}{}>", tag.name, attrs, if empty { "/" } else { "" })
+                    }
+                    Event::End(name) => format!("</{}>", name),
+                    Event::Text(text) => text.into_owned(),
+                })
+            })
+            .collect::<NifResult<_>>()
+            .ok()
+    }
+
+    #[test]
+    fn entities_and_character_references() {
+        assert_eq!(
+            unescape("&lt;&gt;&amp;&quot;&apos;").ok().unwrap(),
+            "<>&\"'"
+        );
+        assert_eq!(
+            unescape("&#x41;&#66;&#x1F600;").ok().unwrap(),
+            "AB\u{1F600}"
+        );
+        assert!(match unescape("plain") {
+            Ok(Cow::Borrowed(text)) => text == "plain",
+            _ => false,
+        });
+        for invalid in &["&nbsp;", "&amp", "&#xD800;", "&#x;", "&#12a;"] {
+            assert!(unescape(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn declarations_comments_and_cdata() {
+        let xml =
+            "\u{feff}<?xml version=\"1.0\"?><!DOCTYPE sst><!-- <b> --><t><![CDATA[<b> &amp;]]></t>";
+        assert_eq!(events(xml).unwrap(), vec!["<t>", "<b> &amp;", "</t>"]);
+    }
+
+    #[test]
+    fn tags_and_attributes() {
+        let xml = r#"<x:row r='1' x:spans="1:2" a = "b>c"><c/><col min="1" />a &lt; b</x:row>"#;
+        assert_eq!(
+            events(xml).unwrap(),
+            vec![
+                "<row r=1 spans=1:2 a=b>c>",
+                "<c/>",
+                "<col min=1/>",
+                "a < b",
+                "</row>"
+            ]
+        );
+    }
+
+    #[test]
+    fn truncated_documents() {
+        for xml in &[
+            "<row",
+            "<row r=\"1",
+            "<row r=1>",
+            "<row r=\"1\"",
+            "<!-- x",
+            "<![CDATA[x",
+            "</row",
+            "a &amp",
+        ] {
+            assert!(events(xml).is_none(), "{}", xml);
+        }
+        let mut reader = XmlReader::new("<a x></a>");
+        assert!(reader.next().unwrap().is_err());
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn text_and_skipping_nested_elements() {
+        let mut reader = XmlReader::new("<t>x<t>y</t>z</t>");
+        reader.next();
+        assert_eq!(reader.read_text("t").ok(), Some("xyz".to_string()));
+
+        let mut reader = XmlReader::new("<a><a/><a>b</a></a><b/>");
+        reader.next();
+        assert!(XmlReader::skip(&mut reader, "a").is_ok());
+        assert_eq!(events(&reader.xml[reader.pos..]).unwrap(), vec!["<b/>"]);
+
+        let mut reader = XmlReader::new("<t>x");
+        reader.next();
+        assert!(reader.read_text("t").is_err());
+    }
+
+    #[test]
+    fn spreadsheet_escapes() {
+        assert_eq!(unescape_text("a_x000D_b".to_string()), "a\rb");
+        assert_eq!(unescape_text("_x005F_x0041_".to_string()), "_x0041_");
+        assert_eq!(unescape_text("_xD800_".to_string()), "_xD800_");
+        assert_eq!(unescape_text("_x41_".to_string()), "_x41_");
+    }
+}
@@ -1,16 +1,27 @@
+use crate::col_width::ColumnWidths;
 use crate::error::ExcelResult;
+use crate::formula::{Shared, SharedFormulas};
 use rustler::dynamic::get_type;
 use rustler::types::ListIterator;
 use rustler::{Term, TermType};
+use std::borrow::Cow;
 use std::collections::HashMap;
-use crate::util::to_excel_coords;
-use crate::wb_compiler::{Border, BorderStyle, CellStyle, Font, SheetCompInfo, WorkbookCompInfo, DB};
-use crate::workbook::{CellValue, Sheet};
-use crate::xml_writer::{Escaped, XmlWriter};
+use std::io::Write;
+use crate::util::{from_excel_coords, to_camel_case, to_excel_coords, Float};
+use crate::wb_compiler::{
+  Border, BorderStyle, CellStyle, Color, Fill, Font, NumFmt, SheetCompInfo, WorkbookCompInfo,
+  DB,
+};
+use crate::workbook::{
+  CellValue, CustomProperty, DocProps, Pane, Sheet, SheetView, StringMode, Workbook,
+};
+use crate::xml_writer::{xml_escape, ControlChars, EscapedText, XmlWriter};
 
 pub fn write_content_types<T: XmlWriter>(
   writer: &mut T,
   scis: &Vec<SheetCompInfo>,
+  custom_props: bool,
+  metadata: bool,
 ) -> ExcelResult<()> {
   writer.write_string(&r###"<?xml version="1.0" encoding="UTF-8"?>
     <Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types">
@@ -21,11 +32,19 @@ pub fn write_content_types<T: XmlWriter>(
     <Override PartName="/xl/workbook.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.sheet.main+xml"/>
     <Override PartName="/xl/styles.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.styles+xml"/>
     "###)?;
+  if custom_props {
+    writer.write_string(&r###"<Override PartName="/docProps/custom.xml" ContentType="application/vnd.openxmlformats-officedocument.custom-properties+xml"/>
+    "###)?;
+  }
   for sci in scis {
     writer.write_string(&format!(r###"
         <Override PartName="/xl/worksheets/{}" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.worksheet+xml"/>
     "###, sci.filename))?;
   }
+  if metadata {
+    writer.write_string(&r###"<Override PartName="/xl/metadata.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.sheetMetadata+xml"/>
+    "###)?;
+  }
   writer.write_string(&r###"
     <Override PartName="/xl/sharedStrings.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.sharedStrings+xml"/>
     </Types>
@@ -36,6 +55,7 @@ pub fn write_xl_rels<T: XmlWriter>(
   writer: &mut T,
   scis: &Vec<SheetCompInfo>,
   next_free_xl_rid: i32,
+  metadata: bool,
 ) -> ExcelResult<()> {
   writer.write_string(&r#"<?xml version="1.0" encoding="UTF-8"?>
       <Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">
@@ -46,30 +66,62 @@ pub fn write_xl_rels<T: XmlWriter>(
   }
   writer.write_string(&format!(r#"
         <Relationship Id="rId{}" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/sharedStrings" Target="sharedStrings.xml"/>
-      </Relationships>
   "#, next_free_xl_rid))?;
+  if metadata {
+    writer.write_string(&format!(r#"<Relationship Id="rId{}" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/sheetMetadata" Target="metadata.xml"/>
+  "#, next_free_xl_rid + 1))?;
+  }
+  writer.write_string(&"</Relationships>")?;
   Ok(())
 }
+
+/// The cell metadata dynamic array formulas refer to with `cm="1"`.
+pub fn dynamic_array_metadata() -> String {
+  r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<metadata xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main" xmlns:xda="http://schemas.microsoft.com/office/spreadsheetml/2017/dynamicarray">
+  <metadataTypes count="1">
+    <metadataType name="XLDAPR" minSupportedVersion="120000" copy="1" pasteAll="1" pasteValues="1" merge="1" splitFirst="1" rowColShift="1" clearFormats="1" clearComments="1" assign="1" coerce="1" cellMeta="1"/>
+  </metadataTypes>
+  <futureMetadata name="XLDAPR" count="1">
+    <bk><extLst><ext uri="{bdbb8cdc-fa1e-496e-a857-3c3f30c029c3}"><xda:dynamicArrayProperties fDynamic="1" fCollapsed="0"/></ext></extLst></bk>
+  </futureMetadata>
+  <cellMetadata count="1">
+    <bk><rc t="1" v="0"/></bk>
+  </cellMetadata>
+</metadata>
+"#.to_string()
+}
 pub fn write_workbook_xml<T: XmlWriter>(
   writer: &mut T,
-  sheets: &Vec<Sheet>,
+  workbook: &Workbook,
   scis: &Vec<SheetCompInfo>,
 ) -> ExcelResult<()> {
   writer.write_string(&r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
     <workbook xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main" xmlns:r="http://schemas.openxmlformats.org/officeDocument/2006/relationships">
     <fileVersion appName="Calc"/>
-    <bookViews>
-      <workbookView activeTab="0"/>
-    </bookViews>
+    "#)?;
+  if workbook.values.dates.date1904 {
+    writer.write_xml_empty_tag(&"workbookPr", vec![(&"date1904", &"1")])?;
+  }
+  writer.write_xml(&"bookViews", vec![], |w| {
+    let mut attrs: Vec<(&ToString, &ToString)> = vec![];
+    for (attr, value) in &workbook.window {
+      attrs.push((attr, value));
+    }
+    attrs.push((&"firstSheet", &workbook.first_visible_tab));
+    attrs.push((&"activeTab", &workbook.active_sheet));
+    w.write_xml_empty_tag(&"workbookView", attrs)
+  })?;
+  writer.write_string(&r#"
     <sheets>
       "#)?;
-  for (sheet, sci) in sheets.iter().zip(scis) {
+  for (sheet, sci) in workbook.sheets.iter().zip(scis) {
     writer.write_xml_empty_tag(
       &"sheet",
       vec![
-        (&"name", &Escaped(&sheet.name)),
+        (&"name", &sheet.name),
         (&"sheetId", &sci.sheet_id),
-        (&"state", &"visible"),
+        (&"state", &sheet.state),
         (&"r:id", &sci.rid),
       ],
     )?;
@@ -82,7 +134,11 @@ pub fn write_workbook_xml<T: XmlWriter>(
 
   Ok(())
 }
-pub fn wite_string_db<T: XmlWriter>(writer: &mut T, stringdb: &DB<String>) -> ExcelResult<()> {
+pub fn wite_string_db<T: XmlWriter>(
+  writer: &mut T,
+  stringdb: &DB<String>,
+  control_chars: ControlChars,
+) -> ExcelResult<()> {
   let list = stringdb.sorted_list();
   let len = list.len();
   writer.write_string(&format!(r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
@@ -91,7 +147,7 @@ pub fn wite_string_db<T: XmlWriter>(writer: &mut T, stringdb: &DB<String>) -> Ex
 
   for (string, _) in list {
     writer.write_string(&"<si><t>")?;
-    writer.write_string(&Escaped(string))?;
+    writer.write_string(&EscapedText(string, control_chars))?;
     writer.write_string(&"</t></si>")?;
   }
   writer.write_string(&"</sst>")?;
@@ -99,19 +155,29 @@ pub fn wite_string_db<T: XmlWriter>(writer: &mut T, stringdb: &DB<String>) -> Ex
 }
 pub fn write_xl_styles<T: XmlWriter>(
   writer: &mut T,
+  workbook: &Workbook,
   wci: &mut WorkbookCompInfo,
 ) -> ExcelResult<()> {
   writer.write_string(&r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
     <styleSheet xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main">"#)?;
   let mut buf: Vec<u8> = Vec::with_capacity(2048);
-  buf.write_string(
-    &r#"      <cellStyleXfs count="1">
-        <xf borderId="0" numFmtId="0" fillId="0" fontId="0" applyAlignment="1">
-          <alignment wrapText="1"/>
-        </xf>
-      </cellStyleXfs>
-  "#,
-  )?;
+  buf.write_string(&format!(
+    r#"      <cellStyleXfs count="{}">"#,
+    wci.named_styles.len()
+  ))?;
+  for named_style in &wci.named_styles {
+    write_cell_style(
+      &mut buf,
+      &named_style.style,
+      None,
+      &mut wci.fontdb,
+      &mut wci.filldb,
+      &mut wci.numfmtdb,
+      &mut wci.borderstyledb,
+    )?;
+    buf.write_string(&"\n")?;
+  }
+  buf.write_string(&"</cellStyleXfs>")?;
   let cell_styles: Vec<(&CellStyle, &i32)> = wci.cellstyledb.sorted_list();
   buf.write_string(&format!(
     r#"      <cellXfs count="{}">
@@ -122,6 +188,7 @@ pub fn write_xl_styles<T: XmlWriter>(
     write_cell_style(
       &mut buf,
       style,
+      Some(wci.get_named_style_id(&style.named_style)),
       &mut wci.fontdb,
       &mut wci.filldb,
       &mut wci.numfmtdb,
@@ -130,13 +197,28 @@ pub fn write_xl_styles<T: XmlWriter>(
     buf.write_string(&"\n")?;
   }
   buf.write_string(&"</cellXfs>")?;
+  buf.write_xml(
+    &"cellStyles",
+    vec![(&"count", &wci.named_styles.len())],
+    |w| {
+      for (xf_id, named_style) in wci.named_styles.iter().enumerate() {
+        let mut attrs: Vec<(&ToString, &ToString)> =
+          vec![(&"name", &named_style.name), (&"xfId", &xf_id)];
+        if let Some(builtin_id) = &named_style.builtin_id {
+          attrs.push((&"builtinId", builtin_id));
+        }
+        w.write_xml_empty_tag(&"cellStyle", attrs)?;
+      }
+      Ok(())
+    },
+  )?;
   write_numfmts(writer, wci.numfmtdb.sorted_list())?;
 
   let font_list = wci.fontdb.sorted_list();
   writer.write_xml(&"fonts", vec![(&"count", &(1 + font_list.len()))], |w| {
-    w.write_string(&"<font />")?;
+    write_font(w, &workbook.default_font)?;
     for (font, _) in font_list {
-      write_font(w, font)?;
+      write_font(w, &font.inherit(&workbook.default_font))?;
       w.write_string(&"\n")?;
     }
     Ok(())
@@ -175,22 +257,15 @@ pub fn write_xl_styles<T: XmlWriter>(
 
   Ok(())
 }
-fn write_border<T: XmlWriter>(writer: &mut T, border: &Border) -> ExcelResult<()> {
-  let style = match border.style.as_str() {
-    "dash_dot" => "dashDot",
-    "dash_dot_dot" => "dashDotDot",
-    any => any,
-  }.to_string();
+fn write_border<T: XmlWriter>(writer: &mut T, tag: &str, border: &Border) -> ExcelResult<()> {
+  let style = to_camel_case(&border.style);
   let mut attrs: Vec<(&ToString, &ToString)> = vec![];
   if style != "" {
     attrs.push((&"style", &style));
   }
-  writer.write_xml(&border.type_, attrs, |w| {
-    if border.color != "" {
-      w.write_string(&format!(
-        "<color rgb=\"{}\" />",
-        to_argb_color(border.color.clone())
-      ))?;
+  writer.write_xml(&tag, attrs, |w| {
+    if border.color != Color::None {
+      w.write_xml_empty_tag(&"color", border.color.get_attributes())?;
     }
     Ok(())
   })
@@ -203,12 +278,12 @@ fn write_border_style<T: XmlWriter>(writer: &mut T, border: &BorderStyle) -> Exc
       (&"diagonalDown", &border.diagonal_down),
     ],
     |w| {
-      write_border(w, &border.left)?;
-      write_border(w, &border.right)?;
-      write_border(w, &border.top)?;
-      write_border(w, &border.bottom)?;
+      write_border(w, "left", &border.left)?;
+      write_border(w, "right", &border.right)?;
+      write_border(w, "top", &border.top)?;
+      write_border(w, "bottom", &border.bottom)?;
       if border.diagonal_down || border.diagonal_up {
-        write_border(w, &border.diagonal)?;
+        write_border(w, "diagonal", &border.diagonal)?;
       } else {
         w.write_string(&"<diagonal></diagonal>")?;
       }
@@ -217,55 +292,97 @@ fn write_border_style<T: XmlWriter>(writer: &mut T, border: &BorderStyle) -> Exc
   )
 }
 
-fn write_fill<T: XmlWriter>(writer: &mut T, fill: &String) -> ExcelResult<()> {
-  writer.write_xml(&"fill", vec![], |w| {
-    if fill != "" {
-      w.write_string(&format!(
-        "<patternFill patternType=\"solid\"><fgColor rgb=\"{}\" /></patternFill>",
-        to_argb_color(fill.clone())
-      ))?;
+fn write_fill<T: XmlWriter>(writer: &mut T, fill: &Fill) -> ExcelResult<()> {
+  writer.write_xml(&"fill", vec![], |w| match fill {
+    Fill::None => Ok(()),
+    Fill::Pattern {
+      pattern_type,
+      fg_color,
+      bg_color,
+    } => w.write_xml(&"patternFill", vec![(&"patternType", pattern_type)], |w| {
+      if *fg_color != Color::None {
+        w.write_xml_empty_tag(&"fgColor", fg_color.get_attributes())?;
+      }
+      if *bg_color != Color::None {
+        w.write_xml_empty_tag(&"bgColor", bg_color.get_attributes())?;
+      }
+      Ok(())
+    }),
+    Fill::Gradient(gradient) => {
+      let mut attrs: Vec<(&ToString, &ToString)> = vec![(&"type", &gradient.type_)];
+      if gradient.type_ == "path" {
+        attrs.push((&"left", &gradient.left));
+        attrs.push((&"right", &gradient.right));
+        attrs.push((&"top", &gradient.top));
+        attrs.push((&"bottom", &gradient.bottom));
+      } else {
+        attrs.push((&"degree", &gradient.degree));
+      }
+      w.write_xml(&"gradientFill", attrs, |w| {
+        for (position, color) in &gradient.stops {
+          w.write_xml(&"stop", vec![(&"position", position)], |w| {
+            w.write_xml_empty_tag(&"color", color.get_attributes())
+          })?;
+        }
+        Ok(())
+      })
     }
-    Ok(())
   })
 }
 fn write_font<T: XmlWriter>(writer: &mut T, font: &Font) -> ExcelResult<()> {
   writer.write_xml(&"font", vec![], |w| {
-    // TODO: Add more underline properties, see here:
-    // http://webapp.docx4java.org/OnlineDemo/ecma376/SpreadsheetML/ST_UnderlineValues.html
-    if font.bold {
+    if font.bold == Some(true) {
       w.write_string(&"<b val=\"1\"/>")?;
     }
-    if font.italic {
+    if font.italic == Some(true) {
       w.write_string(&"<i val=\"1\"/>")?;
     }
-    if font.underline {
-      w.write_string(&"<u val=\"single\"/>")?;
-    }
-    if font.strike {
+    if font.strike == Some(true) {
       w.write_string(&"<strike val=\"1\"/>")?;
     }
-    if font.size > 0 {
-      w.write_string(&format!("<sz val=\"{}\"/>", font.size))?;
+    if font.outline == Some(true) {
+      w.write_string(&"<outline val=\"1\"/>")?;
+    }
+    if font.shadow == Some(true) {
+      w.write_string(&"<shadow val=\"1\"/>")?;
+    }
+    if font.underline != "" {
+      w.write_xml_empty_tag(&"u", vec![(&"val", &font.underline)])?;
+    }
+    if font.vert_align != "" {
+      w.write_xml_empty_tag(&"vertAlign", vec![(&"val", &font.vert_align)])?;
+    }
+    if font.size.0 > 0.0 {
+      w.write_xml_empty_tag(&"sz", vec![(&"val", &font.size)])?;
     }
-    if font.color != "" {
-      w.write_string(&format!(
-        "<color rgb=\"{}\" />",
-        to_argb_color(font.color.clone())
-      ))?;
+    if font.color != Color::None {
+      w.write_xml_empty_tag(&"color", font.color.get_attributes())?;
     }
     if font.font != "" {
-      w.write_string(&format!("<name val=\"{}\" />", font.font))?;
+      w.write_xml_empty_tag(&"name", vec![(&"val", &font.font)])?;
+    }
+    if let Some(family) = &font.family {
+      w.write_xml_empty_tag(&"family", vec![(&"val", family)])?;
+    }
+    if let Some(charset) = &font.charset {
+      w.write_xml_empty_tag(&"charset", vec![(&"val", charset)])?;
+    }
+    if font.scheme != "" {
+      w.write_xml_empty_tag(&"scheme", vec![(&"val", &font.scheme)])?;
     }
 
     Ok(())
   })
 }
 
+/// Writes an `xf`; `xf_id` is the named style a `cellXfs` entry is based on and is left
+/// out for the `cellStyleXfs` entries themselves.
 fn write_cell_style<T: XmlWriter>(
   writer: &mut T,
   style: &CellStyle,
+  xf_id: Option<i32>,
   fontdb: &mut DB<Font>,
-  filldb: &mut DB<String>,
+  filldb: &mut DB<Fill>,
   numfmtdb: &mut DB<String>,
   borderstyledb: &mut DB<BorderStyle>,
 ) -> ExcelResult<()> {
@@ -274,30 +391,32 @@ fn write_cell_style<T: XmlWriter>(
   } else {
     0
   };
-  let fill_id = if style.fill != "" {
+  let fill_id = if style.fill != Fill::None {
     filldb.get_id(&style.fill) + 2
   } else {
     0
   };
 
-  let numfmt_id = if style.numfmt != "" {
-    numfmtdb.get_id(&style.numfmt) + 164
+  let numfmt_id = match &style.numfmt {
+    NumFmt::BuiltIn(id) => *id,
+    NumFmt::Custom(fmt) => numfmtdb.get_id(fmt) + 164,
+  };
+  let border_id = if style.border != Default::default() {
+    borderstyledb.get_id(&style.border) + 1
   } else {
     0
   };
-  let border_id = borderstyledb.get_id(&style.border);
-  let alignment_attrs = style
-    .font
-    .as_ref()
-    .map_or(vec![], |x| x.get_alignment_attributes());
+  let alignment_attrs = style.alignment.get_attributes();
 
   let mut style_attrs: Vec<(&ToString, &ToString)> = vec![
     (&"borderId", &border_id),
     (&"fillId", &fill_id),
     (&"fontId", &font_id),
     (&"numFmtId", &numfmt_id),
-    (&"xfId", &0),
   ];
+  if let Some(xf_id) = &xf_id {
+    style_attrs.push((&"xfId", xf_id));
+  }
   if alignment_attrs.len() > 0 {
     style_attrs.push((&"applyAlignment", &1));
   }
@@ -316,11 +435,11 @@ fn write_numfmts<T: XmlWriter>(writer: &mut T, numfmts: Vec<(&String, &i32)>) ->
   if len > 0 {
     writer.write_xml(&"numFmts", vec![(&"count", &len)], |w| {
       for (fmt, index) in numfmts {
-        w.write_string(&format!(
-          r#"<numFmt numFmtId="{}" formatCode="{}" />\n"#,
-          index + 164,
-          fmt
-        ))?;
+        w.write_xml_empty_tag(
+          &"numFmt",
+          vec![(&"numFmtId", &(index + 164)), (&"formatCode", fmt)],
+        )?;
+        w.write_string(&"\n")?;
       }
       Ok(())
     })?;
@@ -328,84 +447,278 @@ fn write_numfmts<T: XmlWriter>(writer: &mut T, numfmts: Vec<(&String, &i32)>) ->
   Ok(())
 }
 
-pub fn write_sheet<T: XmlWriter>(
+/// What the cells of a sheet are written with, worked out before its rows.
+struct SheetCells {
+  row_styles: HashMap<i32, CellStyle>,
+  col_styles: HashMap<i32, CellStyle>,
+  shared: SharedFormulas,
+  strings: StringMode,
+  control_chars: ControlChars,
+}
+
+pub fn write_sheet<T: Write>(
   writer: &mut T,
   sheet: &Sheet,
+  workbook: &Workbook,
   wci: &mut WorkbookCompInfo,
 ) -> ExcelResult<()> {
   writer.write_string(&r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
     <worksheet xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main" xmlns:r="http://schemas.openxmlformats.org/officeDocument/2006/relationships">
-    <sheetPr filterMode="false">
-      <pageSetUpPr fitToPage="false"/>
-    </sheetPr>
-    <dimension ref="A1"/>
-    <sheetViews>
-    <sheetView workbookViewId="0" 
   "#)?;
-  if !sheet.show_grid_lines {
-    writer.write_string(&" showGridLines=\"0\" ")?;
+  writer.write_xml(&"sheetPr", vec![(&"filterMode", &"false")], |w| {
+    if sheet.view.tab_color != Color::None {
+      w.write_xml_empty_tag(&"tabColor", sheet.view.tab_color.get_attributes())?;
+    }
+    w.write_string(&r#"<pageSetUpPr fitToPage="false"/>"#)?;
+    Ok(())
+  })?;
+  writer.write_string(&r#"<dimension ref="A1"/>"#)?;
+  write_sheet_views(writer, sheet)?;
+  write_sheet_format_pr(writer, sheet)?;
+  let cells = SheetCells {
+    row_styles: resolve_styles(&sheet.row_styles, wci)?,
+    col_styles: resolve_styles(&sheet.col_styles, wci)?,
+    shared: if sheet.shared_formulas {
+      SharedFormulas::find(sheet.rows)?
+    } else {
+      SharedFormulas::new()
+    },
+    strings: sheet.strings.as_ref().unwrap_or(&workbook.strings).for_rows(sheet.rows)?,
+    control_chars: workbook.control_chars,
+  };
+  let mut numbers_as_text = vec![];
+  if sheet.auto_width {
+    // The widths come from measuring the rows, so they are written ahead of `<cols>`.
+    let mut widths = Some(ColumnWidths::new(&workbook.default_font));
+    let mut sheet_data: Vec<u8> = Vec::with_capacity(4096);
+    write_sheet_rows(&mut sheet_data, sheet, &cells, &mut widths, &mut numbers_as_text, wci)?;
+    wrtie_col_widths(writer, sheet, &cells.col_styles, &widths, wci)?;
+    writer.write_string(&r#"<sheetData>"#)?;
+    writer.write_all(&sheet_data)?;
+  } else {
+    wrtie_col_widths(writer, sheet, &cells.col_styles, &None, wci)?;
+    writer.write_string(&r#"<sheetData>"#)?;
+    write_sheet_rows(writer, sheet, &cells, &mut None, &mut numbers_as_text, wci)?;
   }
-  writer.write_string(&">")?;
-  writer.write_string(&make_sheet_view(&sheet))?;
-  writer.write_string(
-    &r#"
-      </sheetView>
-    </sheetViews>
-    <sheetFormatPr defaultRowHeight="12.8"/>
-  "#,
-  )?;
-  wrtie_col_widths(writer, sheet)?;
-  writer.write_string(&r#"<sheetData>"#)?;
-  write_sheet_rows(writer, sheet, wci)?;
   writer.write_string(&r#"</sheetData>"#)?;
   write_merge_cells(writer, &sheet.merge_cells)?;
   writer.write_string(
     &r##"
       <pageMargins left="0.75" right="0.75" top="1" bottom="1.0" header="0.5" footer="0.5"/>
-    </worksheet>
   "##,
   )?;
+  // Keeps Excel from flagging the numbers written as text with its green triangle.
+  if numbers_as_text.len() > 0 {
+    writer.write_xml(&"ignoredErrors", vec![], |w| {
+      w.write_xml_empty_tag(
+        &"ignoredError",
+        vec![(&"sqref", &numbers_as_text.join(" ")), (&"numberStoredAsText", &"1")],
+      )
+    })?;
+  }
+  writer.write_string(&"</worksheet>")?;
   Ok(())
 }
 
-fn make_sheet_view(sheet: &Sheet) -> String {
-  let pane = match sheet.pane_freeze {
-    Some((_, 0)) => "bottomLeft",
-    Some((0, _)) => "topRight",
-    Some((x, y)) if x > 0 && y > 0 => "bottomRight",
-    _ => "",
+fn write_sheet_views<T: XmlWriter>(writer: &mut T, sheet: &Sheet) -> ExcelResult<()> {
+  let view = &sheet.view;
+  let mut attrs: Vec<(&ToString, &ToString)> = vec![];
+  if view.tab_selected {
+    attrs.push((&"tabSelected", &"1"));
+  }
+  if !sheet.show_grid_lines {
+    attrs.push((&"showGridLines", &"0"));
+  }
+  if view.show_formulas {
+    attrs.push((&"showFormulas", &"1"));
+  }
+  if !view.show_headers {
+    attrs.push((&"showRowColHeaders", &"0"));
+  }
+  if !view.show_zeros {
+    attrs.push((&"showZeros", &"0"));
+  }
+  if view.right_to_left {
+    attrs.push((&"rightToLeft", &"1"));
+  }
+  if view.view != "normal" {
+    attrs.push((&"view", &view.view));
+  }
+  // `zoomScale` is the current zoom; Excel also keeps one per kind of view.
+  let view_zoom = match view.view.as_str() {
+    "pageLayout" => "zoomScalePageLayoutView",
+    "pageBreakPreview" => "zoomScaleSheetLayoutView",
+    _ => "zoomScaleNormal",
   };
+  if let Some(zoom) = &view.zoom {
+    attrs.push((&"zoomScale", zoom));
+    attrs.push((&view_zoom, zoom));
+  }
+  attrs.push((&"workbookViewId", &0));
 
-  let (selection_pane_attr, panel_xml) = match sheet.pane_freeze {
-    Some((x, y)) if x > 0 || y > 0 => {
-      let top_left_cell = crate::util::to_excel_coords(x + 1, y + 1);
-      let s = format!("pane=\"{}\"", pane);
-      let p = format!("<pane xSplit=\"{}\" ySplit=\"{}\" topLeftCell=\"{}\" activePane=\"{}\" state=\"frozen\" />", 
-        x, y, top_left_cell, pane);
-      (s, p)
+  writer.write_xml(&"sheetViews", vec![], |w| {
+    w.write_xml(&"sheetView", attrs, |w| match &sheet.pane {
+      Some(pane) => write_pane(w, pane, view),
+      _ => {
+        let active_cell = view.get_active_cell();
+        let sqref = view.get_sqref();
+        w.write_xml_empty_tag(
+          &"selection",
+          vec![(&"activeCell", &active_cell), (&"sqref", &sqref)],
+        )
+      }
+    })
+  })
+}
+
+/// Writes a frozen or split pane, with a selection for each part of the window. The given
+/// active cell and selection go to the part they are in for frozen panes, and to the part
+/// that scrolls for split ones; the other parts select their first cell.
+fn write_pane<T: XmlWriter>(writer: &mut T, pane: &Pane, view: &SheetView) -> ExcelResult<()> {
+  let (x_split, y_split, state, (first_row, first_col)) = match pane {
+    Pane::Frozen(rows, cols) => (*cols, *rows, "frozen", (rows + 1, cols + 1)),
+    Pane::Split(x, y, top_left_cell) => {
+      let first_cell = top_left_cell.as_ref().and_then(|x| from_excel_coords(x));
+      (*x, *y, "split", first_cell.unwrap_or((1, 1)))
     }
-    _ => ("".to_string(), "".to_string()),
+  };
+  let pane_name = |bottom: bool, right: bool| match (bottom, right) {
+    (true, true) => "bottomRight",
+    (true, false) => "bottomLeft",
+    (false, true) => "topRight",
+    _ => "topLeft",
+  };
+  let active_pane = match (pane, from_excel_coords(&view.get_active_cell())) {
+    (Pane::Frozen(..), Some((row, col))) if view.has_selection() => {
+      pane_name(y_split > 0 && row >= first_row, x_split > 0 && col >= first_col)
+    }
+    _ => pane_name(y_split > 0, x_split > 0),
   };
 
-  format!(
-    "{}<selection {} activeCell=\"A1\" sqref=\"A1\" />",
-    selection_pane_attr, panel_xml
-  )
+  let top_left_cell = to_excel_coords(first_row, first_col);
+  let mut pane_attrs: Vec<(&ToString, &ToString)> = vec![];
+  if x_split > 0 {
+    pane_attrs.push((&"xSplit", &x_split));
+  }
+  if y_split > 0 {
+    pane_attrs.push((&"ySplit", &y_split));
+  }
+  pane_attrs.push((&"topLeftCell", &top_left_cell));
+  pane_attrs.push((&"activePane", &active_pane));
+  pane_attrs.push((&"state", &state));
+  writer.write_xml_empty_tag(&"pane", pane_attrs)?;
+
+  let parts = [
+    ("topLeft", active_pane == "topLeft", (1, 1)),
+    ("topRight", x_split > 0, (1, first_col)),
+    ("bottomLeft", y_split > 0, (first_row, 1)),
+    ("bottomRight", x_split > 0 && y_split > 0, (first_row, first_col)),
+  ];
+  for &(name, shown, (row, col)) in parts.iter() {
+    if !shown {
+      continue;
+    }
+    let (active_cell, sqref) = if name == active_pane && view.has_selection() {
+      (view.get_active_cell(), view.get_sqref())
+    } else {
+      (to_excel_coords(row, col), to_excel_coords(row, col))
+    };
+    let mut attrs: Vec<(&ToString, &ToString)> = vec![];
+    if name != "topLeft" {
+      attrs.push((&"pane", &name));
+    }
+    attrs.push((&"activeCell", &active_cell));
+    attrs.push((&"sqref", &sqref));
+    writer.write_xml_empty_tag(&"selection", attrs)?;
+  }
+  Ok(())
+}
+
+fn write_sheet_format_pr<T: XmlWriter>(writer: &mut T, sheet: &Sheet) -> ExcelResult<()> {
+  // Excel's default height for 11pt Calibri.
+  const DEFAULT_ROW_HEIGHT: Float = Float(15.0);
+
+  let row_height = sheet.default_row_height.unwrap_or(DEFAULT_ROW_HEIGHT);
+  let mut attrs: Vec<(&ToString, &ToString)> = vec![];
+  if let Some(col_width) = &sheet.default_col_width {
+    attrs.push((&"defaultColWidth", col_width));
+  }
+  attrs.push((&"defaultRowHeight", &row_height));
+  if sheet.default_row_height.is_some() {
+    attrs.push((&"customHeight", &"1"));
+  }
+  writer.write_xml_empty_tag(&"sheetFormatPr", attrs)
+}
+
+fn resolve_styles(
+  styles: &HashMap<i32, CellStyle>,
+  wci: &mut WorkbookCompInfo,
+) -> ExcelResult<HashMap<i32, CellStyle>> {
+  let mut re = HashMap::new();
+  for (&k, style) in styles {
+    re.insert(k, wci.resolve_named_style(style.clone())?);
+  }
+  Ok(re)
 }
-fn wrtie_col_widths<T: XmlWriter>(writer: &mut T, sheet: &Sheet) -> ExcelResult<()> {
-  if sheet.col_widths.len() > 0 {
+
+/// The `cellXfs` index of `style`; 0 is the unstyled default.
+fn get_style_id(style: &CellStyle, wci: &mut WorkbookCompInfo) -> i32 {
+  if *style == Default::default() {
+    0
+  } else {
+    wci.cellstyledb.get_id(style) + 1
+  }
+}
+
+fn wrtie_col_widths<T: XmlWriter>(
+  writer: &mut T,
+  sheet: &Sheet,
+  col_styles: &HashMap<i32, CellStyle>,
+  widths: &Option<ColumnWidths>,
+  wci: &mut WorkbookCompInfo,
+) -> ExcelResult<()> {
+  // Excel's default width for 11pt Calibri, used for columns without a width of their own.
+  const DEFAULT_COL_WIDTH: Float = Float(9.140625);
+
+  let default_width = sheet.default_col_width.unwrap_or(DEFAULT_COL_WIDTH);
+  let mut cols: Vec<i32> = sheet
+    .col_widths
+    .keys()
+    .chain(col_styles.keys())
+    .chain(sheet.hidden_cols.iter())
+    .cloned()
+    .collect();
+  if let Some(widths) = widths {
+    cols.extend(widths.columns());
+  }
+  cols.sort();
+  cols.dedup();
+  if cols.len() > 0 {
     writer.write_xml(&"cols", vec![], |w| {
-      let mut li = sheet
-        .col_widths
-        .iter()
-        .map(|(&k, &v)| (k, v))
-        .collect::<Vec<(i32, i32)>>();
-      li.sort_by(|a, b| a.0.cmp(&b.0));
-      for (col, width) in li {
-        w.write_string(&format!(
-          r#"<col min="{}" max="{}" width="{}" customWidth="1" />"#,
-          col, col, width
-        ))?;
+      for col in cols {
+        let width = sheet.col_widths.get(&col).cloned();
+        let auto_width = widths.as_ref().and_then(|x| x.get(col));
+        let style_id = col_styles.get(&col).map(|x| get_style_id(x, wci));
+        let mut attrs: Vec<(&ToString, &ToString)> = vec![(&"min", &col), (&"max", &col)];
+        match (&width, &auto_width) {
+          (Some(width), _) => {
+            attrs.push((&"width", width));
+            attrs.push((&"customWidth", &"1"));
+          }
+          (_, Some(width)) => {
+            attrs.push((&"width", width));
+            attrs.push((&"bestFit", &"1"));
+            attrs.push((&"customWidth", &"1"));
+          }
+          _ => attrs.push((&"width", &default_width)),
+        }
+        if let Some(style_id) = &style_id {
+          attrs.push((&"style", style_id));
+        }
+        if sheet.hidden_cols.contains(&col) {
+          attrs.push((&"hidden", &"1"));
+        }
+        w.write_xml_empty_tag(&"col", attrs)?;
       }
       Ok(())
     })?;
@@ -416,17 +729,37 @@ fn wrtie_col_widths<T: XmlWriter>(writer: &mut T, sheet: &Sheet) -> ExcelResult<
 fn write_sheet_rows<T: XmlWriter>(
   writer: &mut T,
   sheet: &Sheet,
+  cells: &SheetCells,
+  widths: &mut Option<ColumnWidths>,
+  numbers_as_text: &mut Vec<String>,
   wci: &mut WorkbookCompInfo,
 ) -> ExcelResult<()> {
   let mut i = 1;
+  let row_styles = &cells.row_styles;
 
   let rows: ListIterator = sheet.rows.decode()?;
   for r in rows {
-    writer.write_xml(&"row", get_row_attr(&i, &sheet.row_heights), |w| {
-      write_sheet_cols(w, &r, i, wci)
+    let style_id = row_styles.get(&i).map(|x| get_style_id(x, wci));
+    writer.write_xml(&"row", get_row_attr(&i, sheet, &style_id), |w| {
+      write_sheet_cols(w, &r, i, cells, widths, numbers_as_text, wci)
     })?;
     i = i + 1;
   }
+
+  // Rows past the data with a style, height or hidden flag are written empty.
+  let mut rest: Vec<i32> = row_styles
+    .keys()
+    .chain(sheet.row_heights.keys())
+    .chain(sheet.hidden_rows.iter())
+    .cloned()
+    .filter(|&x| x >= i)
+    .collect();
+  rest.sort();
+  rest.dedup();
+  for row_index in rest {
+    let style_id = row_styles.get(&row_index).map(|x| get_style_id(x, wci));
+    writer.write_xml_empty_tag(&"row", get_row_attr(&row_index, sheet, &style_id))?;
+  }
   Ok(())
 }
 
@@ -434,15 +767,45 @@ fn write_sheet_cols<'a, T: XmlWriter>(
   writer: &mut T,
   row: &Term<'a>,
   row_index: i32,
+  cells: &SheetCells,
+  widths: &mut Option<ColumnWidths>,
+  numbers_as_text: &mut Vec<String>,
   wci: &mut WorkbookCompInfo,
 ) -> ExcelResult<()> {
   let mut i = 1;
+  let default_style = CellStyle::default();
+  let row_style = cells.row_styles.get(&row_index);
 
   let cols: ListIterator = row.decode()?;
   for cell in cols {
-    let (content, style_id) = split_into_content_style(cell, wci)?;
+    // A row style takes precedence over a column style, as it does in Excel.
+    let parent = match (row_style, cells.col_styles.get(&i)) {
+      (Some(row_style), Some(col_style)) => Cow::Owned(row_style.inherit(col_style)),
+      (Some(style), None) | (None, Some(style)) => Cow::Borrowed(style),
+      (None, None) => Cow::Borrowed(&default_style),
+    };
+    let (content, cell_style) = split_into_content_style(cell, &parent, row_index, wci)?;
+    let style_id = get_style_id(&cell_style, wci);
+    if let Some(widths) = widths {
+      widths.measure(i, &content, &cell_style);
+    }
     let r = to_excel_coords(row_index, i);
+    let content = match content {
+      CellValue::NumberText(num) => {
+        numbers_as_text.push(r.clone());
+        CellValue::String(num)
+      }
+      content => content,
+    };
     match content {
+      CellValue::String(string) if !cells.strings.is_shared(&string) => {
+        writer.write_string(&format!(
+          r##"<c r="{}" s="{}" t="inlineStr"><is><t>{}</t></is></c>"##,
+          r,
+          style_id,
+          EscapedText(&string, cells.control_chars).to_string()
+        ))?;
+      }
       CellValue::String(string) => {
         let id = wci.stringdb.get_id(&string);
         writer.write_string(&format!(
@@ -463,18 +826,47 @@ fn write_sheet_cols<'a, T: XmlWriter>(
           r, style_id, num
         ))?;
       }
-      CellValue::Formula(formular, opts) => {
-        let value = match opts.get("value") {
-          Some(value) => format!("<v>{}</v>", value),
-          _ => "".to_string(),
+      CellValue::Error(error) => {
+        writer.write_string(&format!(
+          r##"<c r="{}" s="{}" t="e">
+              <v>{}</v>
+              </c>"##,
+          r, style_id, error
+        ))?;
+      }
+      CellValue::Formula(formula) => {
+        let (mut cell_attrs, value) = match formula.value.as_ref().map(|x| x.get_type_and_text()) {
+          Some((Some(t), text)) => (format!(r#" t="{}""#, t), format!("<v>{}</v>", xml_escape(text))),
+          Some((None, text)) => ("".to_string(), format!("<v>{}</v>", text)),
+          _ => ("".to_string(), "".to_string()),
+        };
+        // Dynamic arrays are array formulas flagged through the cell metadata; Excel
+        // works out the spill range on load.
+        let array_ref = match (&formula.array_ref, formula.dynamic) {
+          (Some(range), _) => Some(range.clone()),
+          (_, true) => Some(r.clone()),
+          _ => None,
+        };
+        if formula.dynamic {
+          cell_attrs.push_str(r#" cm="1""#);
+          wci.has_dynamic_arrays = true;
+        }
+        let f = match (array_ref, cells.shared.get(row_index, i)) {
+          (Some(range), _) => format!(r#"<f t="array" ref="{}">{}</f>"#, range, xml_escape(formula.text)),
+          (_, Some(Shared::Anchor(si, range))) => format!(
+            r#"<f t="shared" ref="{}" si="{}">{}</f>"#,
+            range, si, xml_escape(formula.text)
+          ),
+          (_, Some(Shared::Member(si))) => format!(r#"<f t="shared" si="{}"/>"#, si),
+          _ => format!("<f>{}</f>", xml_escape(formula.text)),
         };
         writer.write_string(&format!(
           r##"<c r="{}"
-              s="{}">
-              <f>{}</f>
+              s="{}"{}>
+              {}
               {}
               </c>"##,
-          r, style_id, formular, value
+          r, style_id, cell_attrs, f, value
         ))?;
       }
       _ => (),
@@ -484,34 +876,57 @@ fn write_sheet_cols<'a, T: XmlWriter>(
   Ok(())
 }
 
+/// Splits a cell into its value and style; options not given on the cell are
+/// taken from `parent`, the style of its row and column.
 fn split_into_content_style<'a>(
   cell: Term<'a>,
+  parent: &CellStyle,
+  row_index: i32,
   wci: &mut WorkbookCompInfo,
-) -> ExcelResult<(CellValue, i32)> {
-  Ok(match get_type(cell) {
+) -> ExcelResult<(CellValue, CellStyle)> {
+  let (term, cell_style) = match get_type(cell) {
     TermType::List => {
       let mut li: ListIterator = cell.decode()?;
       match li.next() {
-        Some(term) => {
-          let cell_style = CellStyle::new(li)?;
-          let cell_value = CellValue::new(term, cell_style.is_date())?;
-          (cell_value, wci.cellstyledb.get_id(&cell_style) + 1)
-        }
-        _ => (CellValue::None, 0),
+        Some(term) => (term, wci.resolve_named_style(CellStyle::new(li)?)?.inherit(parent)),
+        _ => return Ok((CellValue::None, Default::default())),
       }
     }
-    _ => (CellValue::new(cell, false)?, 0),
+    _ => (cell, parent.clone()),
+  };
+  Ok(match CellValue::new(term, row_index, &wci.values)? {
+    // Dates and times in a cell without a number format get a matching one.
+    CellValue::Date(serial, format) => {
+      let cell_style = if cell_style.numfmt == NumFmt::default() {
+        CellStyle {
+          numfmt: NumFmt::from_format_code(format.to_string()),
+          ..cell_style
+        }
+      } else {
+        cell_style
+      };
+      (CellValue::Number(serial), cell_style)
+    }
+    cell_value => (cell_value, cell_style),
   })
 }
 
 fn get_row_attr<'a>(
   row_index: &'a i32,
-  row_heights: &'a HashMap<i32, i32>,
+  sheet: &'a Sheet,
+  style_id: &'a Option<i32>,
 ) -> Vec<(&'a ToString, &'a ToString)> {
   let mut re: Vec<(&'a ToString, &'a ToString)> = vec![(&"r", row_index)];
-  if let Some(height) = row_heights.get(&row_index) {
+  if let Some(height) = sheet.row_heights.get(&row_index) {
+    re.push((&"ht", height));
     re.push((&"customHeight", &"1"));
-    re.push((&"customHeight", height));
+  }
+  if sheet.hidden_rows.contains(row_index) {
+    re.push((&"hidden", &"1"));
+  }
+  if let Some(style_id) = style_id {
+    re.push((&"s", style_id));
+    re.push((&"customFormat", &"1"));
   }
   re
 }
@@ -522,7 +937,7 @@ fn write_merge_cells<T: XmlWriter>(
   if merge_cells.len() > 0 {
     writer.write_xml(&"mergeCells", vec![(&"count", &merge_cells.len())], |w| {
       for (from, to) in merge_cells {
-        w.write_string(&format!("<mergeCell ref=\"{}:{}\"/>", from, to))?;
+        w.write_xml_empty_tag(&"mergeCell", vec![(&"ref", &format!("{}:{}", from, to))])?;
       }
       Ok(())
     })?;
@@ -530,40 +945,111 @@ fn write_merge_cells<T: XmlWriter>(
   Ok(())
 }
 
-pub fn to_argb_color(color: String) -> String {
-  if color != "" {
-    format!("#{}", color[1..].to_string())
-  } else {
-    "".to_string()
-  }
-}
-pub fn doc_props_app(ver: String) -> String {
-  format!( r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+pub fn write_doc_props_app<T: XmlWriter>(
+  writer: &mut T,
+  doc_props: &DocProps,
+  ver: String,
+) -> ExcelResult<()> {
+  writer.write_string(&r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
 <Properties xmlns="http://schemas.openxmlformats.org/officeDocument/2006/extended-properties" xmlns:vt="http://schemas.openxmlformats.org/officeDocument/2006/docPropsVTypes">
   <TotalTime>0</TotalTime>
-  <Application>Elixlsx</Application>
-  <AppVersion>{}</AppVersion>
-</Properties>
-"#, ver)
+"#)?;
+  let application = doc_props.application.clone().unwrap_or("Elixlsx".to_string());
+  write_text_element(writer, "Application", &Some(application))?;
+  write_text_element(writer, "AppVersion", &Some(ver))?;
+  write_text_element(writer, "Company", &doc_props.company)?;
+  write_text_element(writer, "Manager", &doc_props.manager)?;
+  writer.write_string(&"</Properties>\n")?;
+  Ok(())
 }
 
-pub fn doc_props_core(time: String, language: Option<String>, revision: Option<i32>) -> String {
-  format!( r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+pub fn write_doc_props_core<T: XmlWriter>(
+  writer: &mut T,
+  doc_props: &DocProps,
+  time: &String,
+) -> ExcelResult<()> {
+  writer.write_string(&r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
 <cp:coreProperties xmlns:cp="http://schemas.openxmlformats.org/package/2006/metadata/core-properties" xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:dcterms="http://purl.org/dc/terms/" xmlns:dcmitype="http://purl.org/dc/dcmitype/" xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance">
-  <dcterms:created xsi:type="dcterms:W3CDTF">{}</dcterms:created>
-  <dc:language>{}</dc:language>
-  <dcterms:modified xsi:type="dcterms:W3CDTF">{}</dcterms:modified>
-  <cp:revision>{}</cp:revision>
-</cp:coreProperties>
-"#, time, language.unwrap_or("en-US".to_string()), time, revision.unwrap_or(1))
+"#)?;
+  writer.write_xml(&"dcterms:created", vec![(&"xsi:type", &"dcterms:W3CDTF")], |w| {
+    w.write_string(&xml_escape(time.clone()))?;
+    Ok(())
+  })?;
+  writer.write_xml(&"dcterms:modified", vec![(&"xsi:type", &"dcterms:W3CDTF")], |w| {
+    w.write_string(&xml_escape(time.clone()))?;
+    Ok(())
+  })?;
+  write_text_element(writer, "dc:title", &doc_props.title)?;
+  write_text_element(writer, "dc:subject", &doc_props.subject)?;
+  write_text_element(writer, "dc:creator", &doc_props.creator)?;
+  write_text_element(writer, "cp:lastModifiedBy", &doc_props.last_modified_by)?;
+  write_text_element(writer, "cp:keywords", &doc_props.keywords)?;
+  write_text_element(writer, "cp:category", &doc_props.category)?;
+  write_text_element(writer, "dc:description", &doc_props.description)?;
+  let language = doc_props.language.clone().unwrap_or("en-US".to_string());
+  write_text_element(writer, "dc:language", &Some(language))?;
+  writer.write_string(&"<cp:revision>1</cp:revision>\n</cp:coreProperties>\n")?;
+  Ok(())
 }
 
-pub fn rels_dotrels() -> String {
-  r#"<?xml version="1.0" encoding="UTF-8"?>
+pub fn write_doc_props_custom<T: XmlWriter>(writer: &mut T, doc_props: &DocProps) -> ExcelResult<()> {
+  // The format id Office uses for user defined properties; pids 0 and 1 are reserved.
+  const FMTID: &str = "{D5CDD505-2E9C-101B-9397-08002B2CF9AE}";
+
+  writer.write_string(&r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Properties xmlns="http://schemas.openxmlformats.org/officeDocument/2006/custom-properties" xmlns:vt="http://schemas.openxmlformats.org/officeDocument/2006/docPropsVTypes">
+"#)?;
+  for (i, (name, value)) in doc_props.custom.iter().enumerate() {
+    let pid = i + 2;
+    writer.write_xml(
+      &"property",
+      vec![(&"fmtid", &FMTID), (&"pid", &pid), (&"name", name)],
+      |w| {
+        let (tag, value) = match value {
+          CustomProperty::String(x) => ("vt:lpwstr", xml_escape(x.clone())),
+          CustomProperty::Int(x) => ("vt:i4", x.to_string()),
+          CustomProperty::Float(x) => ("vt:r8", x.to_string()),
+          CustomProperty::Bool(x) => ("vt:bool", x.to_string()),
+          CustomProperty::DateTime(x) => ("vt:filetime", x.clone()),
+        };
+        w.write_xml(&tag, vec![], |w| {
+          w.write_string(&value)?;
+          Ok(())
+        })
+      },
+    )?;
+    writer.write_string(&"\n")?;
+  }
+  writer.write_string(&"</Properties>\n")?;
+  Ok(())
+}
+
+fn write_text_element<T: XmlWriter>(
+  writer: &mut T,
+  tag: &str,
+  text: &Option<String>,
+) -> ExcelResult<()> {
+  if let Some(text) = text {
+    writer.write_xml(&tag, vec![], |w| {
+      w.write_string(&xml_escape(text.clone()))?;
+      Ok(())
+    })?;
+    writer.write_string(&"\n")?;
+  }
+  Ok(())
+}
+
+pub fn rels_dotrels(custom_props: bool) -> String {
+  format!(r#"<?xml version="1.0" encoding="UTF-8"?>
   <Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">
   <Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/officeDocument" Target="xl/workbook.xml"/>
   <Relationship Id="rId2" Type="http://schemas.openxmlformats.org/package/2006/relationships/metadata/core-properties" Target="docProps/core.xml"/>
   <Relationship Id="rId3" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/extended-properties" Target="docProps/app.xml"/>
+  {}
   </Relationships>
-"#.to_string()
+"#, if custom_props {
+    r#"<Relationship Id="rId4" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/custom-properties" Target="docProps/custom.xml"/>"#
+  } else {
+    ""
+  })
 }
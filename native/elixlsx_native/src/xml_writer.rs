@@ -1,5 +1,6 @@
 use crate::error::ExcelResult;
 use regex::Regex;
+use std::fmt;
 use std::io::{Result, Write};
 
 pub trait XmlWriter {
@@ -44,7 +45,7 @@ pub trait XmlWriter {
             self.write_string(&" ")?;
             self.write_string(name)?;
             self.write_string(&"=\"")?;
-            self.write_string(value)?;
+            self.write_string(&Escaped(value))?;
             self.write_string(&"\"")?;
         }
         Ok(())
@@ -59,34 +60,106 @@ impl<T: Write> XmlWriter for T {
 
 pub struct Escaped<'a>(pub &'a ToString);
 
-impl<'a> ToString for Escaped<'a> {
-    fn to_string(&self) -> String {
-        xml_escape(self.0.to_string())
+impl<'a> fmt::Display for Escaped<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&xml_escape(self.0.to_string()))
     }
 }
 
+/// Cell text, where characters XML can't carry are encoded (or dropped) according to
+/// the workbook's `ControlChars` setting.
+pub struct EscapedText<'a>(pub &'a ToString, pub ControlChars);
+
+impl<'a> fmt::Display for EscapedText<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&xml_escape_text(self.0.to_string(), self.1))
+    }
+}
+
+/// What to do with characters that are not allowed in XML 1.0 (e.g. `\x0B`, `\x1F`).
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum ControlChars {
+    /// Write them as SpreadsheetML `_xHHHH_` escapes, which Excel turns back into the character.
+    #[default]
+    Escape,
+    /// Drop them from the output.
+    Strip,
+}
+
+fn is_xml_illegal(c: char) -> bool {
+    match c {
+        '\t' | '\n' | '\r' => false,
+        '\u{0}'..='\u{1F}' | '\u{FFFE}' | '\u{FFFF}' => true,
+        _ => false,
+    }
+}
+
+fn push_escaped(output: &mut String, c: char) {
+    match c {
+        '<' => output.push_str("&lt;"),
+        '>' => output.push_str("&gt;"),
+        '&' => output.push_str("&amp;"),
+        '\'' => output.push_str("&apos;"),
+        '"' => output.push_str("&quot;"),
+        _ => output.push(c),
+    }
+}
+
+/// Escapes markup characters for use in attributes and element content.
+/// Characters that are illegal in XML are dropped.
 pub fn xml_escape(input: String) -> String {
     lazy_static! {
-        static ref REGEX: Regex = Regex::new("[<>&\"']").unwrap();
+        static ref REGEX: Regex =
+            Regex::new("[<>&\"'\\x00-\\x08\\x0B\\x0C\\x0E-\\x1F\\x{FFFE}\\x{FFFF}]").unwrap();
     }
     if let Some(first) = REGEX.find(&input) {
         let first = first.start();
         let len = input.len();
-        let mut output: Vec<u8> = Vec::with_capacity(len + len / 2);
-        output.extend_from_slice(input[0..first].as_bytes());
-        let rest = input[first..].bytes();
-        for c in rest {
-            match c {
-                b'<' => output.extend_from_slice(b"&lt;"),
-                b'>' => output.extend_from_slice(b"&gt;"),
-                b'&' => output.extend_from_slice(b"&amp;"),
-                b'\'' => output.extend_from_slice(b"&apos;"),
-                b'"' => output.extend_from_slice(b"&quot;"),
-                _ => output.push(c),
+        let mut output = String::with_capacity(len + len / 2);
+        output.push_str(&input[0..first]);
+        for c in input[first..].chars() {
+            if !is_xml_illegal(c) {
+                push_escaped(&mut output, c);
             }
         }
-        unsafe { String::from_utf8_unchecked(output) }
+        output
     } else {
         input
     }
 }
+
+/// Escapes cell text. On top of `xml_escape`, illegal characters are written as `_xHHHH_`
+/// (or stripped), and literal `_xHHHH_` sequences get their underscore escaped as `_x005F_`
+/// so Excel doesn't decode them.
+pub fn xml_escape_text(input: String, control_chars: ControlChars) -> String {
+    lazy_static! {
+        static ref REGEX: Regex = Regex::new(
+            "[<>&\"'\\x00-\\x08\\x0B\\x0C\\x0E-\\x1F\\x{FFFE}\\x{FFFF}]|_x[0-9A-Fa-f]{4}_"
+        ).unwrap();
+    }
+    if !REGEX.is_match(&input) {
+        return input;
+    }
+    let len = input.len();
+    let mut output = String::with_capacity(len + len / 2);
+    let mut last = 0;
+    for m in REGEX.find_iter(&input) {
+        output.push_str(&input[last..m.start()]);
+        let matched = m.as_str();
+        if matched.starts_with("_x") {
+            output.push_str("_x005F_");
+            output.push_str(&matched[1..]);
+        } else {
+            for c in matched.chars() {
+                if !is_xml_illegal(c) {
+                    push_escaped(&mut output, c);
+                } else if control_chars == ControlChars::Escape {
+                    output.push_str(&format!("_x{:04X}_", c as u32));
+                }
+            }
+        }
+        last = m.end();
+    }
+    output.push_str(&input[last..]);
+    output
+}
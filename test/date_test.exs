@@ -0,0 +1,111 @@
+defmodule Elixlsx.Native.DateTest do
+  use ExUnit.Case
+  alias Elixlsx.{Workbook, Sheet}
+
+  defp render(cell, options \\ %{}) do
+    files =
+      %Workbook{sheets: [%Sheet{name: "s", rows: [[cell]]}]}
+      |> Map.merge(options)
+      |> Elixlsx.Native.write_excel_nif()
+      |> Enum.into(%{})
+
+    [_, value] = Regex.run(~r{<c r="A1"[^>]*>\s*<v>([^<]*)</v>}, files['xl/worksheets/sheet1.xml'])
+    {value, files['xl/styles.xml']}
+  end
+
+  describe "Elixir calendar types" do
+    test "dates" do
+      {value, styles} = render(~D[2018-01-02])
+      assert value == "43102"
+      assert styles =~ ~s(<numFmt numFmtId="164" formatCode="yyyy-mm-dd"/>)
+    end
+
+    test "naive datetimes keep their microseconds" do
+      {value, styles} = render(~N[2018-01-02 12:00:00.500000])
+      assert value =~ ~r/^43102\.500005787/
+      assert styles =~ ~s(formatCode="yyyy-mm-dd h:mm:ss")
+    end
+
+    test "datetimes are written in UTC, or the workbook's utc_offset" do
+      datetime = %DateTime{
+        year: 2018, month: 1, day: 2, hour: 14, minute: 0, second: 0, microsecond: {0, 0},
+        time_zone: "Europe/Berlin", zone_abbr: "CET", utc_offset: 3600, std_offset: 0
+      }
+
+      assert {"43102.541666666664", _} = render(datetime)
+      assert {"43102.583333333336", _} = render(datetime, %{utc_offset: 3600})
+    end
+
+    test "times are a fraction of a day" do
+      {value, styles} = render(~T[06:00:00])
+      assert value == "0.25"
+      assert styles =~ ~s(numFmtId="21")
+    end
+
+    test "a number format of the cell's own is kept" do
+      {_, styles} = render([~D[2018-01-02], num_format: "d mmm yyyy"])
+      assert styles =~ ~s(formatCode="d mmm yyyy")
+      refute styles =~ ~s(formatCode="yyyy-mm-dd")
+    end
+
+    test "Erlang datetimes no longer need a date format" do
+      {value, styles} = render({{2018, 1, 2}, {6, 0, 0}})
+      assert value == "43102.25"
+      assert styles =~ ~s(formatCode="yyyy-mm-dd h:mm:ss")
+    end
+
+    test "invalid dates are rejected" do
+      assert_raise ArgumentError, fn -> render({{2018, 2, 30}, {0, 0, 0}}) end
+      assert_raise ArgumentError, fn -> render(%{~D[2018-01-02] | year: 4_294_969_314}) end
+    end
+
+    test "offsets out of range are rejected rather than overflowing" do
+      datetime = %DateTime{
+        year: 2018, month: 1, day: 2, hour: 14, minute: 0, second: 0, microsecond: {0, 0},
+        time_zone: "Etc/UTC", zone_abbr: "UTC", utc_offset: 9_223_372_036_854_775_807, std_offset: 1
+      }
+
+      assert_raise ArgumentError, fn -> render(datetime) end
+      assert_raise ArgumentError, fn -> render(%{datetime | utc_offset: 9_223_372_036_854_775}) end
+    end
+  end
+
+  describe "serial numbers" do
+    test "1900 counts as a leap year from March on, as in Excel" do
+      assert {"59.5", _} = render(~N[1900-02-28 12:00:00])
+      assert {"61", _} = render(~D[1900-03-01])
+    end
+
+    test "times of day on their own are day 0" do
+      assert {"0.25", _} = render(~N[1899-12-31 06:00:00])
+    end
+
+    test "dates before the epoch are rejected" do
+      assert_raise ArgumentError, fn -> render(~D[1899-12-30]) end
+      assert_raise ArgumentError, fn -> render(~D[1903-12-31], %{date1904: true}) end
+    end
+
+    test "the 1904 date system" do
+      files =
+        %Workbook{sheets: [%Sheet{name: "s", rows: [[~D[2018-01-02]]]}]}
+        |> Map.put(:date1904, true)
+        |> Elixlsx.Native.write_excel_nif()
+        |> Enum.into(%{})
+
+      assert files['xl/workbook.xml'] =~ ~s(<workbookPr date1904="1"/>)
+      assert files['xl/worksheets/sheet1.xml'] =~ ~r{<v>41640</v>}
+    end
+
+    test "durations" do
+      {value, styles} = render({:duration, 90061})
+      assert value == "1.0423726851851851"
+      assert styles =~ ~s(numFmtId="46")
+
+      duration = %{__struct__: Duration, year: 0, month: 0, week: 0, day: 1, hour: 1, minute: 1, second: 1, microsecond: {0, 0}}
+      assert {"1.0423726851851851", _} = render(duration)
+      assert_raise ArgumentError, fn -> render(%{duration | month: 1}) end
+      assert_raise ArgumentError, fn -> render(%{duration | week: 9_223_372_036_854_775_807}) end
+      assert_raise ArgumentError, fn -> render(%{duration | second: 9_223_372_036_854}) end
+    end
+  end
+end
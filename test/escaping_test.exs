@@ -0,0 +1,89 @@
+defmodule Elixlsx.Native.EscapingTest do
+  use ExUnit.Case
+  alias Elixlsx.{Workbook, Sheet}
+
+  @sheet 'xl/worksheets/sheet1.xml'
+  @styles 'xl/styles.xml'
+  @strings 'xl/sharedStrings.xml'
+
+  defp render(%Workbook{} = workbook) do
+    workbook
+    |> Elixlsx.Native.write_excel_nif()
+    |> Enum.into(%{})
+  end
+
+  defp render(%Workbook{} = workbook, file), do: render(workbook) |> Map.fetch!(file)
+
+  defp render(rows, file), do: render(%Workbook{sheets: [%Sheet{name: "Sheet1", rows: rows}]}, file)
+
+  test "formula text" do
+    xml = render([[{:formula, ~s{IF(A1<5,"x","y")}}]], @sheet)
+    assert xml =~ "<f>IF(A1&lt;5,&quot;x&quot;,&quot;y&quot;)</f>"
+  end
+
+  test "cached formula values" do
+    xml = render([[{:formula, "A2", %{"value" => "a<b & c"}}]], @sheet)
+    assert xml =~ "<v>a&lt;b &amp; c</v>"
+  end
+
+  test "number formats" do
+    xml = render([[["x", num_format: ~s(0.00" <kg>")]]], @styles)
+    assert xml =~ ~s(formatCode="0.00&quot; &lt;kg&gt;&quot;")
+  end
+
+  test "font names" do
+    xml = render([[["x", font: ~s(Corp "Sans" & Co)]]], @styles)
+    assert xml =~ ~s(<name val="Corp &quot;Sans&quot; &amp; Co"/>)
+  end
+
+  test "sheet names" do
+    workbook = %Workbook{sheets: [%Sheet{name: ~s(P&L "2018" <draft>), rows: []}]}
+    assert render(workbook, 'xl/workbook.xml') =~
+             ~s(name="P&amp;L &quot;2018&quot; &lt;draft&gt;")
+  end
+
+  test "merge cells" do
+    workbook = %Workbook{sheets: [%Sheet{name: "s", rows: [], merge_cells: [{"A1", "B2"}]}]}
+    assert render(workbook, @sheet) =~ ~s(<mergeCell ref="A1:B2"/>)
+  end
+
+  test "control characters in cell text are written as _xHHHH_" do
+    xml = render([["a\vb\x1Fc"]], @strings)
+    assert xml =~ "<t>a_x000B_b_x001F_c</t>"
+  end
+
+  test "literal _xHHHH_ sequences in cell text are escaped" do
+    xml = render([["id_x0041_"]], @strings)
+    assert xml =~ "<t>id_x005F_x0041_</t>"
+  end
+
+  test "control characters can be stripped" do
+    workbook =
+      %Workbook{sheets: [%Sheet{name: "s", rows: [["a\vb\x00c"]]}]}
+      |> Map.put(:control_chars, :strip)
+
+    assert render(workbook, @strings) =~ "<t>abc</t>"
+  end
+
+  test "every part is well-formed XML" do
+    nasty = ~s(<&>"'\v\x01)
+    # Formula text has to parse, so here it goes in a string literal.
+    formula = "\"" <> String.replace(nasty, "\"", "\"\"") <> "\""
+
+    workbook = %Workbook{
+      sheets: [
+        %Sheet{
+          name: nasty,
+          rows: [
+            [nasty, [nasty, font: nasty, num_format: nasty], {:formula, formula, %{"value" => nasty}}]
+          ]
+        }
+      ]
+    }
+
+    for {name, xml} <- render(workbook) do
+      assert {_, rest} = :xmerl_scan.string(String.to_charlist(xml), quiet: true)
+      assert String.trim(to_string(rest)) == "", "trailing content in #{name}"
+    end
+  end
+end
@@ -0,0 +1,156 @@
+defmodule Elixlsx.Native.FormulaTest do
+  use ExUnit.Case
+  alias Elixlsx.{Workbook, Sheet}
+
+  defp sheet_xml(rows) do
+    %Workbook{sheets: [%Sheet{name: "s", rows: rows}]}
+    |> Elixlsx.Native.write_excel_nif()
+    |> Enum.into(%{})
+    |> Map.fetch!('xl/worksheets/sheet1.xml')
+  end
+
+  defp cell(xml) do
+    [cell] = Regex.run(~r(<c r="A1".*?</c>)s, xml)
+    String.replace(cell, ~r/\s+</, "<")
+  end
+
+  defp shared_xml(rows) do
+    %Workbook{sheets: [%Sheet{name: "s", rows: rows} |> Map.put(:shared_formulas, true)]}
+    |> Elixlsx.Native.write_excel_nif()
+    |> Enum.into(%{})
+    |> Map.fetch!('xl/worksheets/sheet1.xml')
+    |> String.replace(~r/\s+</, "<")
+    |> String.replace(~r/\s+/, " ")
+  end
+
+  describe "cached values" do
+    test "numbers" do
+      assert cell(sheet_xml([[{:formula, "1+1", value: 2}]])) =~ ~s(s="0"><f>1+1</f><v>2</v></c>)
+      assert cell(sheet_xml([[{:formula, "1/4", %{value: 0.25}}]])) =~ "<v>0.25</v>"
+    end
+
+    test "strings" do
+      assert cell(sheet_xml([[{:formula, ~s(A2&"x"), value: "a & x"}]])) =~
+               ~s(s="0" t="str"><f>A2&amp;&quot;x&quot;</f><v>a &amp; x</v></c>)
+    end
+
+    test "booleans" do
+      assert cell(sheet_xml([[{:formula, "A2>1", value: false}]])) =~ ~s(t="b"><f>A2&gt;1</f><v>0</v>)
+    end
+
+    test "errors" do
+      assert cell(sheet_xml([[{:formula, "1/0", value: {:error, "#DIV/0!"}}]])) =~ ~s(t="e"><f>1/0</f><v>#DIV/0!</v>)
+      assert_raise ArgumentError, fn -> sheet_xml([[{:formula, "1/0", value: {:error, "#OOPS"}}]]) end
+    end
+
+    test "no value" do
+      assert cell(sheet_xml([[{:formula, "NOW()"}]])) =~ ~s(s="0"><f>NOW\(\)</f></c>)
+      assert cell(sheet_xml([[{:formula, "NOW()", value: nil}]])) =~ ~s(s="0"><f>NOW\(\)</f></c>)
+    end
+  end
+
+  test "number cells" do
+    xml = sheet_xml([[42, 0.5, -1.0e300]])
+    assert xml =~ ~r(<c r="A1" s="0" t="n">\s*<v>42</v>)
+    assert xml =~ ~r(<c r="B1" s="0" t="n">\s*<v>0.5</v>)
+    assert xml =~ ~r(<c r="C1" s="0" t="n">\s*<v>-1E300</v>)
+  end
+
+  describe "array formulas" do
+    test "legacy array formulas" do
+      assert cell(sheet_xml([[{:array_formula, "=A2:A4*B2:B4", "A1:A3"}]])) =~
+               ~s(<f t="array" ref="A1:A3">A2:A4*B2:B4</f></c>)
+
+      assert cell(sheet_xml([[{:array_formula, "SUM(A2:A4*B2:B4)", "A1", value: 12}]])) =~
+               ~s(<f t="array" ref="A1">SUM\(A2:A4*B2:B4\)</f><v>12</v>)
+
+      assert_raise ArgumentError, fn -> sheet_xml([[{:array_formula, "A2:A4", "nope"}]]) end
+    end
+
+    test "dynamic array functions spill" do
+      files =
+        %Workbook{sheets: [%Sheet{name: "s", rows: [[{:formula, ~s{=SORT(UNIQUE(FILTER(B:B, C:C="x")))}}]]}]}
+        |> Elixlsx.Native.write_excel_nif()
+        |> Enum.into(%{})
+
+      assert cell(files['xl/worksheets/sheet1.xml']) =~
+               ~s(s="0" cm="1"><f t="array" ref="A1">_xlfn._xlws.SORT\(_xlfn.UNIQUE\(_xlfn._xlws.FILTER\(B:B, C:C=&quot;x&quot;\)\)\)</f></c>)
+
+      assert files['xl/metadata.xml'] =~ ~s(<xda:dynamicArrayProperties fDynamic="1" fCollapsed="0"/>)
+      assert files['xl/_rels/workbook.xml.rels'] =~ ~s(Target="metadata.xml")
+      assert files['[Content_Types].xml'] =~ ~s(PartName="/xl/metadata.xml")
+    end
+
+    test "prefixed names, strings and sheet names are left alone" do
+      xml = sheet_xml([[{:formula, ~s{_xlfn.UNIQUE('SORT(x'!A:A&"FILTER(")}}]])
+      assert cell(xml) =~ ~s(<f t="array" ref="A1">_xlfn.UNIQUE\('SORT\(x'!A:A&amp;&quot;FILTER\(&quot;\)</f>)
+    end
+
+    test "no metadata part without dynamic arrays" do
+      files =
+        %Workbook{sheets: [%Sheet{name: "s", rows: [[{:formula, "SUM(A2:A3)"}]]}]}
+        |> Elixlsx.Native.write_excel_nif()
+        |> Enum.into(%{})
+
+      refute Map.has_key?(files, 'xl/metadata.xml')
+      refute cell(files['xl/worksheets/sheet1.xml']) =~ "cm="
+    end
+  end
+
+  describe "shared formulas" do
+    test "runs down a column are written once" do
+      rows = for i <- 2..4, do: [i, i * 2, {:formula, "=A#{i}*B#{i}+$A$1"}]
+      xml = shared_xml([["x"] | rows])
+
+      assert xml =~ ~s(<c r="C2" s="0"><f t="shared" ref="C2:C4" si="0">A2*B2+$A$1</f></c>)
+      assert xml =~ ~s(<c r="C3" s="0"><f t="shared" si="0"/></c>)
+      assert xml =~ ~s(<c r="C4" s="0"><f t="shared" si="0"/></c>)
+    end
+
+    test "different formulas break the run" do
+      xml = shared_xml([[{:formula, "B1*2"}], [{:formula, "B2*3"}], [{:formula, "B3*3", value: 6}]])
+
+      assert xml =~ ~s(<c r="A1" s="0"><f>B1*2</f></c>)
+      assert xml =~ ~s(<f t="shared" ref="A2:A3" si="0">B2*3</f>)
+      assert xml =~ ~s(<c r="A3" s="0"><f t="shared" si="0"/><v>6</v></c>)
+    end
+
+    test "strings, absolute rows and whole rows are not shifted" do
+      xml = shared_xml([[{:formula, ~s{"B1"&B$1}}], [{:formula, ~s{"B1"&B$1}}]])
+      assert xml =~ ~s(<c r="A1" s="0"><f>&quot;B1&quot;&amp;B$1</f></c>)
+
+      xml = shared_xml([[{:formula, "SUM(1:1)"}], [{:formula, "SUM(2:2)"}]])
+      refute xml =~ "shared"
+    end
+
+    test "only when asked for" do
+      refute sheet_xml([[{:formula, "B1"}], [{:formula, "B2"}]]) =~ "shared"
+    end
+  end
+
+  describe "checking and rewriting" do
+    test "malformed formulas are rejected" do
+      for formula <- ["SUM(A1:A3", "A1)", ~s("open), "{1,2", "A1 ~ B1", "="] do
+        assert_raise ArgumentError, fn -> sheet_xml([[{:formula, formula}]]) end
+      end
+    end
+
+    test "newer functions get their prefix" do
+      assert cell(sheet_xml([[{:formula, "=IFS(A2>1,1,TRUE,XLOOKUP(B2,C:C,D:D))"}]])) =~
+               ~s{<f>_xlfn.IFS(A2&gt;1,1,TRUE,_xlfn.XLOOKUP(B2,C:C,D:D))</f>}
+
+      assert cell(sheet_xml([[{:formula, ~s{_xlfn.STDEV.S(A2:A9)&"IFS("&'CONCAT(x'!A1}}]])) =~
+               ~s{<f>_xlfn.STDEV.S(A2:A9)&amp;&quot;IFS(&quot;&amp;'CONCAT(x'!A1</f>}
+
+      refute cell(sheet_xml([[{:formula, "XLOOKUP(A2,B:B,C:C)"}]])) =~ "cm="
+    end
+
+    test "templates are filled in for each row" do
+      xml = sheet_xml([["x"], [{:formula_template, "=B{row}*C{row-1}+$D$1"}], [{:formula_template, "B{row}", value: 1}]])
+      assert xml =~ ~r{<c r="A2"\s+s="0">\s*<f>B2\*C1\+\$D\$1</f>}
+      assert xml =~ ~r{<c r="A3"\s+s="0">\s*<f>B3</f>\s*<v>1</v>}
+
+      assert_raise ArgumentError, fn -> sheet_xml([[{:formula_template, "B{row-1}"}]]) end
+    end
+  end
+end
@@ -0,0 +1,88 @@
+defmodule Elixlsx.Native.NumberTest do
+  use ExUnit.Case
+  alias Elixlsx.{Workbook, Sheet}
+
+  defp render(row, options \\ %{}) do
+    files =
+      %Workbook{sheets: [%Sheet{name: "s", rows: [row]}]}
+      |> Map.merge(options)
+      |> Elixlsx.Native.write_excel_nif()
+      |> Enum.into(%{})
+
+    {String.replace(files['xl/worksheets/sheet1.xml'], ~r/\s+</, "<"), files['xl/sharedStrings.xml']}
+  end
+
+  # Built by hand, as the decimal package isn't a dependency.
+  defp decimal(sign, coef, exp), do: %{__struct__: Decimal, sign: sign, coef: coef, exp: exp}
+
+  describe "decimals" do
+    test "are written exactly" do
+      {xml, _} = render([decimal(1, 1999, -2), decimal(-1, 5, -3), decimal(1, 12, 2), decimal(1, 12500, -3)])
+      assert xml =~ ~s(<c r="A1" s="0" t="n"><v>19.99</v>)
+      assert xml =~ ~s(<c r="B1" s="0" t="n"><v>-0.005</v>)
+      assert xml =~ ~s(<c r="C1" s="0" t="n"><v>1200</v>)
+      assert xml =~ ~s(<c r="D1" s="0" t="n"><v>12.5</v>)
+    end
+
+    test "as cached formula values" do
+      {xml, _} = render([{:formula, "B1*2", value: decimal(1, 250, -2)}])
+      assert xml =~ "<f>B1*2</f><v>2.5</v>"
+    end
+  end
+
+  describe "big integers" do
+    test "keep all their digits" do
+      {xml, _} = render([18_446_744_073_709_551_616, -123_456_789_012_345_678_901_234])
+      assert xml =~ "<v>18446744073709551616</v>"
+      assert xml =~ "<v>-123456789012345678901234</v>"
+    end
+
+    test "can be written as text instead of being rounded" do
+      row = [1_234_567_890_123_456_789, decimal(1, 12_345_678_901_234_567, -2), 123_456_789_012_345, 1.0e20]
+      {xml, strings} = render(row, %{long_numbers: :text})
+
+      assert xml =~ ~r{<c r="A1" s="0" t="s">}
+      assert xml =~ ~r{<c r="B1" s="0" t="s">}
+      assert xml =~ ~s(<c r="C1" s="0" t="n"><v>123456789012345</v>)
+      assert xml =~ ~s(<c r="D1" s="0" t="n">)
+      assert strings =~ "<t>1234567890123456789</t>"
+      assert strings =~ "<t>123456789012345.67</t>"
+      assert xml =~ ~s(<ignoredErrors><ignoredError sqref="A1 B1" numberStoredAsText="1"/></ignoredErrors>)
+    end
+
+    test "only flag the cells written as text" do
+      {xml, _} = render([123, "1234567890123456789"], %{long_numbers: :text})
+      refute xml =~ "ignoredErrors"
+    end
+  end
+
+  describe "error values" do
+    test "are written as error cells" do
+      {xml, _} = render([{:error, "#N/A"}, {:error, "#div/0!"}, {:error, :"#REF!"}])
+      assert xml =~ ~s(<c r="A1" s="0" t="e"><v>#N/A</v></c>)
+      assert xml =~ ~s(<c r="B1" s="0" t="e"><v>#DIV/0!</v></c>)
+      assert xml =~ ~s(<c r="C1" s="0" t="e"><v>#REF!</v></c>)
+
+      assert_raise ArgumentError, fn -> render([{:error, "#OOPS"}]) end
+    end
+
+    test "NaN and infinities become #NUM! and #DIV/0!" do
+      {xml, _} = render([:nan, :infinity, :neg_infinity, decimal(1, :NaN, 0), decimal(-1, :inf, 0)])
+      assert xml =~ ~s(<c r="A1" s="0" t="e"><v>#NUM!</v></c>)
+      assert xml =~ ~s(<c r="B1" s="0" t="e"><v>#DIV/0!</v></c>)
+      assert xml =~ ~s(<c r="C1" s="0" t="e"><v>#DIV/0!</v></c>)
+      assert xml =~ ~s(<c r="D1" s="0" t="e"><v>#NUM!</v></c>)
+      assert xml =~ ~s(<c r="E1" s="0" t="e"><v>#DIV/0!</v></c>)
+
+      {xml, _} = render([{:formula, "0/0", value: :nan}])
+      assert xml =~ ~s(t="e"><f>0/0</f><v>#NUM!</v>)
+    end
+
+    test "nil is left out and :empty writes an empty cell" do
+      {xml, _} = render([nil, :empty, 1])
+      refute xml =~ ~s(r="A1")
+      assert xml =~ ~s(<c r="B1" s="0"></c>)
+      assert xml =~ ~s(<c r="C1" s="0" t="n"><v>1</v></c>)
+    end
+  end
+end
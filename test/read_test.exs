@@ -0,0 +1,116 @@
+defmodule Elixlsx.Native.ReadTest do
+  use ExUnit.Case
+  alias Elixlsx.{Workbook, Sheet}
+
+  defp round_trip(%Workbook{} = workbook) do
+    {:ok, {_, xlsx}} = Elixlsx.Native.write_excel(workbook)
+    {:ok, workbook} = Elixlsx.Native.read_excel(xlsx)
+    workbook
+  end
+
+  defp xlsx(sheet_data, strings) do
+    files = [
+      {'[Content_Types].xml', ~s(<?xml version="1.0"?><Types/>)},
+      {'xl/workbook.xml',
+       ~s(<workbook xmlns:r="r"><sheets><sheet name="Data" sheetId="1" r:id="rId1"/></sheets></workbook>)},
+      {'xl/_rels/workbook.xml.rels',
+       ~s(<Relationships><Relationship Id="rId1" Type="x/worksheet" Target="worksheets/sheet1.xml"/>) <>
+         ~s(<Relationship Id="rId2" Type="x/sharedStrings" Target="/xl/sharedStrings.xml"/></Relationships>)},
+      {'xl/sharedStrings.xml', "<sst>#{strings}</sst>"},
+      {'xl/worksheets/sheet1.xml', "<worksheet><sheetData>#{sheet_data}</sheetData></worksheet>"}
+    ]
+
+    {:ok, {_, xlsx}} = :zip.create('test.xlsx', files, [:memory])
+    xlsx
+  end
+
+  defp package(sheet_data, strings \\ "") do
+    {:ok, %Workbook{sheets: [sheet]}} = Elixlsx.Native.read_excel(xlsx(sheet_data, strings))
+    sheet
+  end
+
+  test "values and styles come back as they were written" do
+    rows = [
+      ["text", 42, 1.5, {:error, "#N/A"}],
+      [["bold", bold: true], {:formula, "B1*2", value: 84}, nil, [:empty, num_format: "#,##0.000"]]
+    ]
+
+    %Workbook{sheets: [sheet]} = round_trip(%Workbook{sheets: [%Sheet{name: "s", rows: rows}]})
+    assert sheet.name == "s"
+    assert sheet.rows == rows
+  end
+
+  test "sheet layout" do
+    sheet =
+      %Sheet{
+        name: "Layout",
+        rows: [[1, 2], [3, 4]],
+        col_widths: %{1 => 20.5},
+        row_heights: %{2 => 30},
+        merge_cells: [{"A1", "B1"}],
+        pane_freeze: {1, 0}
+      }
+      |> Map.merge(%{hidden_cols: [2], state: :hidden})
+
+    workbook = %Workbook{sheets: [%Sheet{name: "First", rows: [["a"]]}, sheet]} |> round_trip()
+    [_, read] = workbook.sheets
+    assert read.name == "Layout"
+    assert read.merge_cells == [{"A1", "B1"}]
+    assert read.pane_freeze == {1, 0}
+    assert read.col_widths[1] == 20.5
+    assert read.row_heights[2] == 30.0
+    assert read.hidden_cols == [2]
+    assert read.state == :hidden
+  end
+
+  test "shared formulas are filled into their members" do
+    sheet =
+      package("""
+      <row r="1"><c r="A1"><f t="shared" ref="A1:A3" si="0">B1*$C$1</f><v>1</v></c></row>
+      <row r="2"><c r="A2"><f t="shared" si="0"/><v>2</v></c></row>
+      <row r="4"><c r="B4"><f t="array" ref="B4:B5">C4:C5*2</f></c></row>
+      """)
+
+    assert sheet.rows == [
+             [{:formula, "B1*$C$1", value: 1}],
+             [{:formula, "B2*$C$1", value: 2}],
+             [],
+             [nil, {:array_formula, "C4:C5*2", "B4:B5"}]
+           ]
+  end
+
+  test "rich, inline and escaped text" do
+    sheet =
+      package(
+        """
+        <row r="1"><c r="A1" t="s"><v>0</v></c><c r="B1" t="inlineStr"><is><t>in &amp; line</t></is></c></row>
+        <row r="2"><c r="A2" t="s"><v>1</v></c><c r="B2" t="b"><v>1</v></c></row>
+        """,
+        "<si><r><t>rich </t></r><r><rPr><b/></rPr><t>text</t></r><rPh><t>hint</t></rPh></si>" <>
+          "<si><t>tab_x0009_bed</t></si>"
+      )
+
+    assert sheet.rows == [["rich text", "in & line"], ["tab\tbed", true]]
+  end
+
+  test "rows and columns outside the sheet are rejected" do
+    for sheet_data <- [
+          ~s(<row r="1048577"/>),
+          ~s(<row r="0"/>),
+          ~s(<row r="1"><c r="XFE1"><v>1</v></c></row>)
+        ] do
+      assert {:error, :invalid_xlsx} = Elixlsx.Native.read_excel(xlsx(sheet_data, ""))
+    end
+  end
+
+  test "reading from a path, and files that aren't xlsx" do
+    path = Path.join(System.tmp_dir!(), "read_test.xlsx")
+    {:ok, {_, xlsx}} = Elixlsx.Native.write_excel(%Workbook{sheets: [%Sheet{name: "s", rows: [[1]]}]})
+    File.write!(path, xlsx)
+
+    assert {:ok, %Workbook{sheets: [%Sheet{rows: [[1]]}]}} = Elixlsx.Native.read_excel(path)
+    assert {:error, _} = Elixlsx.Native.read_excel(path <> ".missing")
+    assert {:error, _} = Elixlsx.Native.read_excel(<<"PK", 3, 4, "not a zip">>)
+    assert {:error, :enoent} = Elixlsx.Native.read_excel("PK-missing.xlsx")
+  end
+end
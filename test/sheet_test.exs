@@ -0,0 +1,175 @@
+defmodule Elixlsx.Native.SheetTest do
+  use ExUnit.Case
+  alias Elixlsx.{Workbook, Sheet}
+
+  defp sheet_xml(%Sheet{} = sheet) do
+    %Workbook{sheets: [sheet]}
+    |> Elixlsx.Native.write_excel_nif()
+    |> Enum.into(%{})
+    |> Map.fetch!('xl/worksheets/sheet1.xml')
+  end
+
+  describe "sizes" do
+    test "fractional column widths and row heights" do
+      xml = sheet_xml(%Sheet{name: "s", rows: [["a"]], col_widths: %{1 => 8.43}, row_heights: %{1 => 20.25}})
+      assert xml =~ ~s(<col min="1" max="1" width="8.43" customWidth="1"/>)
+      assert xml =~ ~s(<row r="1" ht="20.25" customHeight="1">)
+    end
+
+    test "default sizes" do
+      assert sheet_xml(%Sheet{name: "s", rows: []}) =~ ~s(<sheetFormatPr defaultRowHeight="15"/>)
+
+      xml =
+        %Sheet{name: "s", rows: []}
+        |> Map.merge(%{default_col_width: 12.5, default_row_height: 18})
+        |> sheet_xml()
+
+      assert xml =~ ~s(<sheetFormatPr defaultColWidth="12.5" defaultRowHeight="18" customHeight="1"/>)
+    end
+
+    test "have to be positive numbers" do
+      for size <- [%{default_col_width: 0}, %{default_row_height: -1}, %{default_col_width: "wide"}] do
+        assert_raise ArgumentError, fn -> %Sheet{name: "s", rows: []} |> Map.merge(size) |> sheet_xml() end
+      end
+    end
+  end
+
+  describe "hidden rows and columns" do
+    test "are flagged, including rows past the data" do
+      xml =
+        %Sheet{name: "s", rows: [["a"], ["b"]], col_widths: %{2 => 30}}
+        |> Map.merge(%{hidden_rows: [2, 5], hidden_cols: [2, 4]})
+        |> sheet_xml()
+
+      assert xml =~ ~s(<row r="2" hidden="1">)
+      assert xml =~ ~s(<row r="5" hidden="1"/>)
+      assert xml =~ ~s(<col min="2" max="2" width="30" customWidth="1" hidden="1"/>)
+      assert xml =~ ~s(<col min="4" max="4" width="9.140625" hidden="1"/>)
+    end
+  end
+
+  describe "auto_width" do
+    defp widths(xml) do
+      for [_, col, width] <- Regex.scan(~r{<col min="(\d+)" max="\d+" width="([\d.]+)"}, xml),
+          into: %{},
+          do: {String.to_integer(col), String.to_float(width)}
+    end
+
+    test "fits columns to their widest text" do
+      rows = [["a", "Quarterly revenue", "x"], ["longer text", "b"]]
+      xml = %Sheet{name: "s", rows: rows} |> Map.put(:auto_width, true) |> sheet_xml()
+
+      assert xml =~ ~s(bestFit="1" customWidth="1")
+      widths = widths(xml)
+      assert widths[2] > widths[1]
+      assert widths[1] > widths[3]
+    end
+
+    test "takes font size and bold into account" do
+      rows = [["Total", ["Total", bold: true], ["Total", size: 22]]]
+      widths = %Sheet{name: "s", rows: rows} |> Map.put(:auto_width, true) |> sheet_xml() |> widths()
+
+      assert widths[2] > widths[1]
+      assert widths[3] > widths[2]
+    end
+
+    test "wrapped text is as wide as its longest line" do
+      rows = [["one\ntwo", ["one\ntwo", wrap_text: true]]]
+      widths = %Sheet{name: "s", rows: rows} |> Map.put(:auto_width, true) |> sheet_xml() |> widths()
+      assert widths[2] < widths[1]
+    end
+
+    test "numbers are measured as displayed" do
+      rows = [[[{{2018, 1, 2}, {0, 0, 0}}, yyyymmdd: true]]]
+      xml = %Sheet{name: "s", rows: rows} |> Map.put(:auto_width, true) |> sheet_xml()
+      assert xml =~ ~s(<col min="1" max="1" width="10.1171875" bestFit="1" customWidth="1"/>)
+    end
+
+    test "explicit widths win" do
+      xml = %Sheet{name: "s", rows: [["some text"]], col_widths: %{1 => 5}} |> Map.put(:auto_width, true) |> sheet_xml()
+      assert xml =~ ~s(<col min="1" max="1" width="5" customWidth="1"/>)
+    end
+  end
+
+  describe "sheet view" do
+    test "zoom, right-to-left and what is shown" do
+      xml =
+        %Sheet{name: "s", rows: []}
+        |> Map.merge(%{zoom: 150, right_to_left: true, show_formulas: true, show_zeros: false, show_headers: false})
+        |> sheet_xml()
+
+      assert xml =~
+               ~s(<sheetView showFormulas="1" showRowColHeaders="0" showZeros="0" rightToLeft="1" zoomScale="150" zoomScaleNormal="150" workbookViewId="0">)
+    end
+
+    test "page layout and page break preview" do
+      xml = %Sheet{name: "s", rows: []} |> Map.merge(%{view: :page_layout, zoom: 80}) |> sheet_xml()
+      assert xml =~ ~s(view="pageLayout" zoomScale="80" zoomScalePageLayoutView="80")
+
+      xml = %Sheet{name: "s", rows: []} |> Map.put(:view, :page_break_preview) |> sheet_xml()
+      assert xml =~ ~s(view="pageBreakPreview")
+    end
+
+    test "tab color" do
+      xml = %Sheet{name: "s", rows: []} |> Map.put(:tab_color, "#FF0000") |> sheet_xml()
+      assert xml =~ ~s(<sheetPr filterMode="false"><tabColor rgb="FFFF0000"/><pageSetUpPr)
+    end
+
+    test "active cell and selection" do
+      xml = %Sheet{name: "s", rows: []} |> Map.put(:active_cell, "C3") |> sheet_xml()
+      assert xml =~ ~s(<selection activeCell="C3" sqref="C3"/>)
+
+      xml = %Sheet{name: "s", rows: []} |> Map.put(:selection, ["B2:D4", "F6"]) |> sheet_xml()
+      assert xml =~ ~s(<selection activeCell="B2" sqref="B2:D4 F6"/>)
+    end
+
+    test "invalid options are rejected" do
+      assert_raise ArgumentError, fn -> %Sheet{name: "s", rows: []} |> Map.put(:zoom, 1000) |> sheet_xml() end
+      assert_raise ArgumentError, fn -> %Sheet{name: "s", rows: []} |> Map.put(:active_cell, "nope") |> sheet_xml() end
+    end
+  end
+
+  describe "panes" do
+    test "frozen rows and columns, with a selection per pane" do
+      xml = %Sheet{name: "s", rows: [], pane_freeze: {1, 2}} |> sheet_xml()
+
+      assert xml =~
+               ~s(<pane xSplit="2" ySplit="1" topLeftCell="C2" activePane="bottomRight" state="frozen"/>) <>
+                 ~s(<selection pane="topRight" activeCell="C1" sqref="C1"/>) <>
+                 ~s(<selection pane="bottomLeft" activeCell="A2" sqref="A2"/>) <>
+                 ~s(<selection pane="bottomRight" activeCell="C2" sqref="C2"/>)
+    end
+
+    test "frozen header row" do
+      xml = %Sheet{name: "s", rows: [], pane_freeze: {1, 0}} |> sheet_xml()
+
+      assert xml =~
+               ~s(<pane ySplit="1" topLeftCell="A2" activePane="bottomLeft" state="frozen"/><selection pane="bottomLeft" activeCell="A2" sqref="A2"/></sheetView>)
+    end
+
+    test "the selection goes to the pane it is in" do
+      xml = %Sheet{name: "s", rows: [], pane_freeze: {1, 0}} |> Map.put(:selection, "B1:C1") |> sheet_xml()
+
+      assert xml =~
+               ~s(activePane="topLeft" state="frozen"/><selection activeCell="B1" sqref="B1:C1"/><selection pane="bottomLeft" activeCell="A2" sqref="A2"/>)
+    end
+
+    test "split panes" do
+      xml = %Sheet{name: "s", rows: []} |> Map.put(:pane_split, {2400, 1800, "D6"}) |> sheet_xml()
+
+      assert xml =~
+               ~s(<pane xSplit="2400" ySplit="1800" topLeftCell="D6" activePane="bottomRight" state="split"/>)
+
+      assert xml =~ ~s(<selection pane="bottomRight" activeCell="D6" sqref="D6"/>)
+
+      xml = %Sheet{name: "s", rows: []} |> Map.put(:pane_split, {0, 1800}) |> sheet_xml()
+      assert xml =~ ~s(<pane ySplit="1800" topLeftCell="A1" activePane="bottomLeft" state="split"/>)
+    end
+
+    test "malformed, negative or conflicting panes are rejected" do
+      for pane <- [%{pane_freeze: 1}, %{pane_freeze: {-1, 2}}, %{pane_freeze: {1, 0}, pane_split: {0, 1800}}] do
+        assert_raise ArgumentError, fn -> %Sheet{name: "s", rows: []} |> Map.merge(pane) |> sheet_xml() end
+      end
+    end
+  end
+end
@@ -0,0 +1,49 @@
+defmodule Elixlsx.Native.StringsTest do
+  use ExUnit.Case
+  alias Elixlsx.{Workbook, Sheet}
+
+  defp render(workbook) do
+    files =
+      workbook
+      |> Elixlsx.Native.write_excel_nif()
+      |> Enum.into(%{})
+
+    {files['xl/worksheets/sheet1.xml'], files['xl/sharedStrings.xml']}
+  end
+
+  @rows [["apple", "pear"], ["apple", ["a & b", bold: true]]]
+
+  test "strings are shared by default" do
+    {xml, strings} = render(%Workbook{sheets: [%Sheet{name: "s", rows: @rows}]})
+    refute xml =~ "inlineStr"
+    assert strings =~ "<t>pear</t>"
+  end
+
+  test "inline strings are written into the cells" do
+    workbook = %Workbook{sheets: [%Sheet{name: "s", rows: @rows}]} |> Map.put(:strings, :inline)
+    {xml, strings} = render(workbook)
+    assert xml =~ ~s(<c r="A1" s="0" t="inlineStr"><is><t>apple</t></is></c>)
+    assert xml =~ ~r{<c r="B2" s="\d+" t="inlineStr"><is><t>a &amp; b</t></is></c>}
+    refute strings =~ "<t>apple</t>"
+  end
+
+  test "adaptive shares only the strings seen more than once" do
+    workbook = %Workbook{sheets: [%Sheet{name: "s", rows: @rows}]} |> Map.put(:strings, :adaptive)
+    {xml, strings} = render(workbook)
+    assert xml =~ ~r{<c r="A1" s="0" t="s">}
+    assert xml =~ ~r{<c r="A2" s="0" t="s">}
+    assert xml =~ ~s(<c r="B1" s="0" t="inlineStr"><is><t>pear</t></is></c>)
+    assert strings =~ "<t>apple</t>"
+    refute strings =~ "<t>pear</t>"
+  end
+
+  test "a sheet's option overrides the workbook's" do
+    sheet = %Sheet{name: "s", rows: @rows} |> Map.put(:strings, :shared)
+    {xml, _} = render(%Workbook{sheets: [sheet]} |> Map.put(:strings, :inline))
+    refute xml =~ "inlineStr"
+
+    assert_raise ArgumentError, fn ->
+      render(%Workbook{sheets: [%Sheet{name: "s", rows: @rows}]} |> Map.put(:strings, :other))
+    end
+  end
+end
@@ -0,0 +1,253 @@
+defmodule Elixlsx.Native.StylesTest do
+  use ExUnit.Case
+  alias Elixlsx.{Workbook, Sheet}
+
+  defp styles(rows) do
+    %Workbook{sheets: [%Sheet{name: "Sheet1", rows: rows}]}
+    |> Elixlsx.Native.write_excel_nif()
+    |> Enum.into(%{})
+    |> Map.fetch!('xl/styles.xml')
+  end
+
+  describe "colors" do
+    test "#RRGGBB gets an opaque alpha channel" do
+      assert styles([[["x", color: "#ff0000"]]]) =~ ~s(<color rgb="FFFF0000"/>)
+    end
+
+    test "#AARRGGBB is kept" do
+      assert styles([[["x", bg_color: "#80FF0000"]]]) =~ ~s(<fgColor rgb="80FF0000"/>)
+    end
+
+    test "named colors" do
+      assert styles([[["x", color: "navy"]]]) =~ ~s(<color rgb="FF000080"/>)
+    end
+
+    test "theme colors with tint" do
+      assert styles([[["x", bg_color: {:theme, 4, 0.4}]]]) =~ ~s(<fgColor theme="4" tint="0.4"/>)
+      assert styles([[["x", color: {:theme, 1}]]]) =~ ~s(<color theme="1"/>)
+    end
+
+    test "indexed colors" do
+      xml = styles([[["x", border: [bottom: [style: :thin, color: {:indexed, 10}]]]]])
+      assert xml =~ ~s(<bottom style="thin"><color indexed="10"/></bottom>)
+    end
+
+    test "malformed colors are rejected" do
+      for color <- [{}, {:theme}, {:hsl, 1, 2}, "#12345"] do
+        assert_raise ArgumentError, fn -> styles([[["x", color: color]]]) end
+      end
+    end
+  end
+
+  describe "fills" do
+    test "bg_color is a solid fill" do
+      assert styles([[["x", bg_color: "#FFFF00"]]]) =~
+               ~s(<fill><patternFill patternType="solid"><fgColor rgb="FFFFFF00"/></patternFill></fill>)
+    end
+
+    test "pattern fills with foreground and background colors" do
+      xml = styles([[["x", fill: [pattern: :dark_grid, fg_color: "red", bg_color: "white"]]]])
+
+      assert xml =~
+               ~s(<patternFill patternType="darkGrid"><fgColor rgb="FFFF0000"/><bgColor rgb="FFFFFFFF"/></patternFill>)
+
+      assert styles([[["x", fill: [pattern: "gray0625"]]]]) =~ ~s(patternType="gray0625")
+    end
+
+    test "gradient fills" do
+      xml = styles([[["x", fill: [gradient: :linear, degree: 90, stops: [{0, "white"}, {1, "blue"}]]]]])
+
+      assert xml =~
+               ~s(<gradientFill type="linear" degree="90"><stop position="0"><color rgb="FFFFFFFF"/></stop><stop position="1"><color rgb="FF0000FF"/></stop></gradientFill>)
+
+      xml = styles([[["x", fill: [gradient: :path, left: 0.5, right: 0.5, top: 0.5, bottom: 0.5, stops: [{0, "white"}, {1, "blue"}]]]]])
+      assert xml =~ ~s(<gradientFill type="path" left="0.5" right="0.5" top="0.5" bottom="0.5">)
+    end
+
+    test "gradient stops need a position between 0 and 1 and a color" do
+      for stops <- [[], [{1.5, "white"}], [{-1, "white"}], [{0, nil}]] do
+        assert_raise ArgumentError, fn -> styles([[["x", fill: [gradient: :linear, stops: stops]]]]) end
+      end
+    end
+  end
+
+  describe "fonts" do
+    test "the default font is written at index 0 and fills in cell fonts" do
+      xml = styles([[["x", bold: true]]])
+
+      assert xml =~
+               ~s(<fonts count="2"><font><sz val="11"/><name val="Calibri"/><family val="2"/><scheme val="minor"/></font>)
+
+      assert xml =~
+               ~s(<font><b val="1"/><sz val="11"/><name val="Calibri"/><family val="2"/><scheme val="minor"/></font>)
+    end
+
+    test "workbook default font" do
+      xml =
+        %Workbook{sheets: [%Sheet{name: "Sheet1", rows: [[["x", size: 10.5]]]}]}
+        |> Map.put(:default_font, font: "Arial", size: 9)
+        |> Elixlsx.Native.write_excel_nif()
+        |> Enum.into(%{})
+        |> Map.fetch!('xl/styles.xml')
+
+      assert xml =~ ~s(<font><sz val="9"/><name val="Arial"/></font>)
+      assert xml =~ ~s(<font><sz val="10.5"/><name val="Arial"/></font>)
+    end
+
+    test "underline, vertical alignment, outline and shadow" do
+      xml = styles([[["x", underline: :double_accounting, superscript: true, outline: true, shadow: true]]])
+      assert xml =~ ~s(<outline val="1"/><shadow val="1"/><u val="doubleAccounting"/><vertAlign val="superscript"/>)
+    end
+
+    test "family, scheme and charset" do
+      xml = styles([[["x", font: "Cambria", family: 1, scheme: :major, charset: 0]]])
+      assert xml =~ ~s(<name val="Cambria"/><family val="1"/><charset val="0"/><scheme val="major"/>)
+    end
+  end
+
+  describe "borders" do
+    test "cells point past the empty default border" do
+      xml = styles([[["x", border: [bottom: [style: :thin]]], ["y", bold: true]]])
+      assert xml =~ ~s(<borders count="2"><border />)
+      assert xml =~ ~r(<cellXfs count="3">.*<xf borderId="1".*<xf borderId="0")s
+    end
+
+    test "styles are camel cased" do
+      xml = styles([[["x", border: [left: [style: :medium_dashed], bottom: [style: :slant_dash_dot]]]]])
+      assert xml =~ ~s(<left style="mediumDashed"></left>)
+      assert xml =~ ~s(<bottom style="slantDashDot"></bottom>)
+    end
+  end
+
+  describe "alignment" do
+    test "horizontal and vertical modes" do
+      xml = styles([[["x", align_horizontal: :center_continuous, align_vertical: :distributed, wrap_text: true]]])
+      assert xml =~ ~s(<alignment horizontal="centerContinuous" vertical="distributed" wrapText="1"/>)
+    end
+
+    test "text rotation" do
+      assert styles([[["x", text_rotation: 45]]]) =~ ~s(<alignment textRotation="45"/>)
+      assert styles([[["x", text_rotation: -45]]]) =~ ~s(<alignment textRotation="135"/>)
+      assert styles([[["x", text_rotation: :vertical]]]) =~ ~s(<alignment textRotation="255"/>)
+    end
+
+    test "indent, shrink to fit, justify last line and reading order" do
+      xml = styles([[["x", indent: 2, shrink_to_fit: true, justify_last_line: true, reading_order: :right_to_left]]])
+      assert xml =~ ~s(<alignment indent="2" justifyLastLine="1" shrinkToFit="1" readingOrder="2"/>)
+    end
+
+    test "alignment does not create a font" do
+      assert styles([[["x", wrap_text: true]]]) =~ ~s(<fonts count="1">)
+    end
+  end
+
+  describe "number formats" do
+    test "built-in formats reuse their reserved ids" do
+      xml = styles([[["x", num_format: "0.00"], ["y", num_format: "mm-dd-yy"]]])
+      assert xml =~ ~s(numFmtId="2")
+      assert xml =~ ~s(numFmtId="14")
+      refute xml =~ "<numFmts"
+    end
+
+    test "numeric ids are used as is" do
+      assert styles([[["x", num_format: 27]]]) =~ ~s(numFmtId="27")
+    end
+
+    test "ids without a built-in format are rejected" do
+      for id <- [-1, 23, 59, 164] do
+        assert_raise ArgumentError, fn -> styles([[["x", num_format: id]]]) end
+      end
+    end
+
+    test "custom formats start at 164" do
+      xml = styles([[["x", num_format: "0.000"]]])
+      assert xml =~ ~s(<numFmt numFmtId="164" formatCode="0.000"/>)
+      assert xml =~ ~s(numFmtId="164")
+    end
+  end
+
+  describe "named styles" do
+    test "Normal is always defined" do
+      xml = styles([["x"]])
+      assert xml =~ ~s(<cellStyle name="Normal" xfId="0" builtinId="0"/>)
+    end
+
+    test "built-in styles are added when used" do
+      xml = styles([[["x", cell_style: "Good"]]])
+      assert xml =~ ~s(<cellStyleXfs count="2">)
+      assert xml =~ ~s(<cellStyle name="Good" xfId="1" builtinId="26"/>)
+      assert xml =~ ~s(<fgColor rgb="FFC6EFCE"/>)
+      assert xml =~ ~r(<cellXfs count="2">.*<xf borderId="0" fillId="2" fontId="1" numFmtId="0" xfId="1">)s
+    end
+
+    test "custom styles are always listed and cells inherit from them" do
+      xml =
+        %Workbook{sheets: [%Sheet{name: "Sheet1", rows: [[["x", cell_style: "Corporate", italic: true]]]}]}
+        |> Map.put(:cell_styles, [
+          {"Corporate", [bold: true, size: 14, font: "Arial", bg_color: "#003366"]},
+          {"Unused", [strike: true]}
+        ])
+        |> Elixlsx.Native.write_excel_nif()
+        |> Enum.into(%{})
+        |> Map.fetch!('xl/styles.xml')
+
+      assert xml =~ ~s(<cellStyle name="Corporate" xfId="1"/>)
+      assert xml =~ ~s(<cellStyle name="Unused" xfId="2"/>)
+      assert xml =~ ~s(<fgColor rgb="FF003366"/>)
+      # The cell's italic is added to Corporate's font rather than replacing it.
+      assert xml =~ ~s(<font><b val="1"/><i val="1"/><sz val="14"/><name val="Arial"/></font>)
+    end
+
+    test "cells can turn off what their style turns on" do
+      xml = styles([[["x", cell_style: "Title", bold: false]]])
+      assert xml =~ ~s(<font><b val="1"/><sz val="18"/><color rgb="FF1F497D"/>)
+      assert xml =~ ~s(<font><sz val="18"/><color rgb="FF1F497D"/>)
+    end
+
+    test "unknown styles are rejected" do
+      assert_raise ArgumentError, fn -> styles([[["x", cell_style: "No such style"]]]) end
+    end
+  end
+
+  describe "column and row styles" do
+    defp render_sheet(sheet) do
+      files =
+        %Workbook{sheets: [sheet]}
+        |> Elixlsx.Native.write_excel_nif()
+        |> Enum.into(%{})
+
+      {files['xl/worksheets/sheet1.xml'], files['xl/styles.xml']}
+    end
+
+    test "columns get a style attribute" do
+      sheet =
+        %Sheet{name: "s", rows: [], col_widths: %{1 => 20}}
+        |> Map.put(:col_styles, %{2 => [num_format: "0.00"]})
+
+      {xml, styles} = render_sheet(sheet)
+      assert xml =~ ~s(<col min="1" max="1" width="20" customWidth="1"/>)
+      assert xml =~ ~s(<col min="2" max="2" width="9.140625" style="1"/>)
+      assert styles =~ ~s(<xf borderId="0" fillId="0" fontId="0" numFmtId="2" xfId="0">)
+    end
+
+    test "rows get a style, including rows past the data" do
+      sheet = %Sheet{name: "s", rows: [["a"]]} |> Map.put(:row_styles, %{1 => [bold: true], 3 => [bold: true]})
+
+      {xml, _} = render_sheet(sheet)
+      assert xml =~ ~s(<row r="1" s="1" customFormat="1">)
+      assert xml =~ ~s(<row r="3" s="1" customFormat="1"/>)
+    end
+
+    test "cells inherit the row style, then the column style" do
+      sheet =
+        %Sheet{name: "s", rows: [["a", ["x", italic: true]], [{{2018, 1, 2}, {0, 0, 0}}]]}
+        |> Map.put(:row_styles, %{1 => [bg_color: "#FFFF00"]})
+        |> Map.put(:col_styles, %{1 => [num_format: "yyyy-mm-dd"]})
+
+      {xml, styles} = render_sheet(sheet)
+      assert xml =~ ~r{<c r="A2" s="(\d+)" t="n">\s*<v>43102</v>}
+      assert styles =~ ~s(<xf borderId="0" fillId="2" fontId="0" numFmtId="164" xfId="0">)
+      assert styles =~ ~s(<xf borderId="0" fillId="2" fontId="1" numFmtId="0" xfId="0">)
+    end
+  end
+end
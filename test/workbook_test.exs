@@ -0,0 +1,128 @@
+defmodule Elixlsx.Native.WorkbookTest do
+  use ExUnit.Case
+  alias Elixlsx.{Workbook, Sheet}
+
+  defp render(%Workbook{} = workbook) do
+    workbook
+    |> Elixlsx.Native.write_excel_nif()
+    |> Enum.into(%{})
+  end
+
+  defp sheets(states) do
+    for {state, i} <- Enum.with_index(states, 1) do
+      Map.put(%Sheet{name: "Sheet#{i}", rows: []}, :state, state)
+    end
+  end
+
+  describe "sheet visibility and the workbook view" do
+    test "hidden and very hidden sheets" do
+      xml = render(%Workbook{sheets: sheets([:visible, :hidden, :very_hidden])})['xl/workbook.xml']
+      assert xml =~ ~s(<sheet name="Sheet1" sheetId="1" state="visible" r:id="rId2"/>)
+      assert xml =~ ~s(<sheet name="Sheet2" sheetId="2" state="hidden" r:id="rId3"/>)
+      assert xml =~ ~s(<sheet name="Sheet3" sheetId="3" state="veryHidden" r:id="rId4"/>)
+    end
+
+    test "the first visible sheet is active by default" do
+      files = render(%Workbook{sheets: sheets([:hidden, :visible])})
+      assert files['xl/workbook.xml'] =~ ~s(<workbookView firstSheet="1" activeTab="1"/>)
+      assert files['xl/worksheets/sheet2.xml'] =~ ~s(<sheetView tabSelected="1")
+      refute files['xl/worksheets/sheet1.xml'] =~ ~s(tabSelected)
+    end
+
+    test "active sheet by index or name, first visible tab and window" do
+      workbook =
+        %Workbook{sheets: sheets([:visible, :visible, :visible])}
+        |> Map.merge(%{active_sheet: "Sheet3", first_visible_tab: 1, window: [x: 120, y: 60, width: 24000, height: 12000]})
+
+      assert render(workbook)['xl/workbook.xml'] =~
+               ~s(<workbookView xWindow="120" yWindow="60" windowWidth="24000" windowHeight="12000" firstSheet="1" activeTab="2"/>)
+
+      workbook = Map.put(workbook, :active_sheet, 1)
+      assert render(workbook)['xl/workbook.xml'] =~ ~s(activeTab="1")
+    end
+
+    test "a workbook needs a visible active sheet" do
+      assert_raise ArgumentError, fn -> render(%Workbook{sheets: sheets([:hidden])}) end
+
+      assert_raise ArgumentError, fn ->
+        render(%Workbook{sheets: sheets([:visible, :hidden])} |> Map.put(:active_sheet, 1))
+      end
+
+      assert_raise ArgumentError, fn ->
+        render(%Workbook{sheets: sheets([:visible])} |> Map.put(:active_sheet, "Nope"))
+      end
+    end
+  end
+
+  describe "document properties" do
+    test "core and app properties are written and escaped" do
+      files =
+        %Workbook{sheets: [%Sheet{name: "s", rows: []}]}
+        |> Map.merge(%{
+          title: "Q3 <draft>",
+          subject: "Revenue",
+          creator: "Finance & Co",
+          last_modified_by: "Reviewer",
+          keywords: ["revenue", "quarterly"],
+          category: "Reports",
+          description: "Quarterly numbers",
+          company: "ACME",
+          manager: "Jane",
+          application: "Reporting"
+        })
+        |> render()
+
+      core = files['docProps/core.xml']
+      assert core =~ "<dc:title>Q3 &lt;draft&gt;</dc:title>"
+      assert core =~ "<dc:subject>Revenue</dc:subject>"
+      assert core =~ "<dc:creator>Finance &amp; Co</dc:creator>"
+      assert core =~ "<cp:lastModifiedBy>Reviewer</cp:lastModifiedBy>"
+      assert core =~ "<cp:keywords>revenue; quarterly</cp:keywords>"
+      assert core =~ "<cp:category>Reports</cp:category>"
+      assert core =~ "<dc:description>Quarterly numbers</dc:description>"
+
+      app = files['docProps/app.xml']
+      assert app =~ "<Application>Reporting</Application>"
+      assert app =~ "<Company>ACME</Company>"
+      assert app =~ "<Manager>Jane</Manager>"
+    end
+
+    test "custom properties are typed" do
+      files =
+        %Workbook{sheets: [%Sheet{name: "s", rows: []}]}
+        |> Map.put(:custom_properties, [
+          {"Classification", "Internal"},
+          {"Version", 3},
+          {"Rate", 0.25},
+          {"Approved", true},
+          {"Reviewed", {{2018, 1, 2}, {3, 4, 5}}}
+        ])
+        |> render()
+
+      custom = files['docProps/custom.xml']
+      fmtid = ~s(fmtid="{D5CDD505-2E9C-101B-9397-08002B2CF9AE}")
+      assert custom =~ ~s(<property #{fmtid} pid="2" name="Classification"><vt:lpwstr>Internal</vt:lpwstr></property>)
+      assert custom =~ ~s(pid="3" name="Version"><vt:i4>3</vt:i4>)
+      assert custom =~ ~s(pid="4" name="Rate"><vt:r8>0.25</vt:r8>)
+      assert custom =~ ~s(pid="5" name="Approved"><vt:bool>true</vt:bool>)
+      assert custom =~ ~s(pid="6" name="Reviewed"><vt:filetime>2018-01-02T03:04:05Z</vt:filetime>)
+
+      assert files['[Content_Types].xml'] =~ ~s(PartName="/docProps/custom.xml")
+      assert files['_rels/.rels'] =~ ~s(Target="docProps/custom.xml")
+    end
+
+    test "custom property names have to be unique" do
+      for properties <- [[{"Version", 3}, {"Version", 4}], %{"Version" => 3, Version: 4}] do
+        assert_raise ArgumentError, fn ->
+          %Workbook{sheets: [%Sheet{name: "s", rows: []}]} |> Map.put(:custom_properties, properties) |> render()
+        end
+      end
+    end
+
+    test "custom.xml is only written when there are custom properties" do
+      files = render(%Workbook{sheets: [%Sheet{name: "s", rows: []}]})
+      refute Map.has_key?(files, 'docProps/custom.xml')
+      refute files['[Content_Types].xml'] =~ "custom.xml"
+    end
+  end
+end